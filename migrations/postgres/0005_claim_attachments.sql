@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS claim_attachments
+(
+    identity_provider TEXT NOT NULL,
+    claim             TEXT NOT NULL,
+    operator          TEXT NOT NULL,
+    value             TEXT NOT NULL,
+    policy_id         TEXT NOT NULL,
+    PRIMARY KEY (identity_provider, claim, operator, value, policy_id)
+);
+
+ALTER TABLE refresh_tokens ADD COLUMN claims TEXT;
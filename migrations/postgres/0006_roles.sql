@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS roles
+(
+    id       TEXT NOT NULL PRIMARY KEY,
+    policies TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS role_attachments
+(
+    identity_provider TEXT NOT NULL,
+    user_id           TEXT NOT NULL,
+    role_id           TEXT NOT NULL,
+    PRIMARY KEY (identity_provider, user_id, role_id)
+);
+
+CREATE TABLE IF NOT EXISTS claim_role_attachments
+(
+    identity_provider TEXT NOT NULL,
+    claim             TEXT NOT NULL,
+    operator          TEXT NOT NULL,
+    value             TEXT NOT NULL,
+    role_id           TEXT NOT NULL,
+    PRIMARY KEY (identity_provider, claim, operator, value, role_id)
+);
@@ -0,0 +1 @@
+ALTER TABLE policies ADD COLUMN template BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS policy_revisions
+(
+    policy_id  TEXT    NOT NULL,
+    revision   BIGINT  NOT NULL,
+    content    TEXT    NOT NULL,
+    template   BOOLEAN NOT NULL,
+    author     TEXT,
+    reason     TEXT,
+    created_at BIGINT  NOT NULL,
+    PRIMARY KEY (policy_id, revision)
+);
+
+INSERT INTO policy_revisions (policy_id, revision, content, template, created_at)
+SELECT id, 1, content, template, EXTRACT(EPOCH FROM NOW())::BIGINT
+FROM policies;
+
+ALTER TABLE policy_attachments ADD COLUMN revision BIGINT;
+ALTER TABLE claim_attachments ADD COLUMN revision BIGINT;
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS policies
+(
+    id      TEXT NOT NULL PRIMARY KEY,
+    content TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS identities
+(
+    identity_provider TEXT NOT NULL,
+    user_id           TEXT NOT NULL,
+    PRIMARY KEY (identity_provider, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS policy_attachments
+(
+    identity_provider TEXT NOT NULL,
+    user_id           TEXT NOT NULL,
+    policy_id         TEXT NOT NULL,
+    PRIMARY KEY (identity_provider, user_id, policy_id)
+);
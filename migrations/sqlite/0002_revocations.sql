@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS revocations
+(
+    target_type       TEXT    NOT NULL,
+    identity_provider TEXT    NOT NULL,
+    target_id         TEXT    NOT NULL,
+    revoked_at        INTEGER NOT NULL,
+    expires_at        INTEGER NOT NULL,
+    PRIMARY KEY (target_type, identity_provider, target_id)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS refresh_tokens
+(
+    token_hash        TEXT    NOT NULL PRIMARY KEY,
+    family_id         TEXT    NOT NULL,
+    identity_provider TEXT    NOT NULL,
+    user_id           TEXT    NOT NULL,
+    issued_at         INTEGER NOT NULL,
+    expires_at        INTEGER NOT NULL,
+    used              BOOLEAN NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_id ON refresh_tokens (family_id);
@@ -0,0 +1,59 @@
+-- The version of a policy is its current revision
+ALTER TABLE policies ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+UPDATE policies
+SET version = COALESCE((SELECT MAX(revision) FROM policy_revisions WHERE policy_revisions.policy_id = policies.id), 1);
+
+-- Versions of the other entities are kept in separate tables, so they outlive the deletion of the entity
+-- and a recreated entity never reuses the version of a deleted one
+CREATE TABLE IF NOT EXISTS identity_versions
+(
+    identity_provider TEXT    NOT NULL,
+    user_id           TEXT    NOT NULL,
+    version           INTEGER NOT NULL,
+    PRIMARY KEY (identity_provider, user_id)
+);
+
+INSERT INTO identity_versions (identity_provider, user_id, version)
+SELECT identity_provider, user_id, 1
+FROM identities;
+
+CREATE TABLE IF NOT EXISTS role_versions
+(
+    id      TEXT    NOT NULL PRIMARY KEY,
+    version INTEGER NOT NULL
+);
+
+INSERT INTO role_versions (id, version)
+SELECT id, 1
+FROM roles;
+
+-- Attachments are stored as one row per attached policy or role
+CREATE TABLE IF NOT EXISTS attachment_versions
+(
+    identity_provider TEXT    NOT NULL,
+    user_id           TEXT    NOT NULL,
+    version           INTEGER NOT NULL,
+    PRIMARY KEY (identity_provider, user_id)
+);
+
+INSERT INTO attachment_versions (identity_provider, user_id, version)
+SELECT identity_provider, user_id, 1
+FROM (SELECT identity_provider, user_id FROM policy_attachments
+      UNION
+      SELECT identity_provider, user_id FROM role_attachments);
+
+CREATE TABLE IF NOT EXISTS claim_attachment_versions
+(
+    identity_provider TEXT    NOT NULL,
+    claim             TEXT    NOT NULL,
+    operator          TEXT    NOT NULL,
+    value             TEXT    NOT NULL,
+    version           INTEGER NOT NULL,
+    PRIMARY KEY (identity_provider, claim, operator, value)
+);
+
+INSERT INTO claim_attachment_versions (identity_provider, claim, operator, value, version)
+SELECT identity_provider, claim, operator, value, 1
+FROM (SELECT identity_provider, claim, operator, value FROM claim_attachments
+      UNION
+      SELECT identity_provider, claim, operator, value FROM claim_role_attachments);
@@ -1,24 +1,27 @@
+use crate::http::problem_details::ProblemDetails;
 use crate::models::external::token::ExternalToken;
-use actix_web::http::header::HeaderValue;
+use crate::services::admin_authorizer::AuthorizationError;
+use crate::services::base::upsert_repository::RepositoryError;
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
 use anyhow::bail;
+use log::error;
 
 impl TryFrom<&HeaderValue> for ExternalToken {
     type Error = anyhow::Error;
 
-    // Compiler will complain that `return Err` statements in this code are unreachable,
-    // but this is not true. See test cases below.
-    #[allow(unreachable_code)]
     fn try_from(value: &HeaderValue) -> Result<Self, Self::Error> {
         match value.to_str() {
             Ok(string_value) => {
                 let tokens = string_value.split(" ").collect::<Vec<&str>>();
 
                 if tokens.len() != 2 {
-                    return Err(bail!("Invalid token format"));
+                    bail!("Invalid token format");
                 }
 
                 if tokens[0] != "Bearer" {
-                    return Err(bail!("Invalid token format"));
+                    bail!("Invalid token format");
                 }
 
                 Ok(ExternalToken::from(tokens[1].to_owned()))
@@ -28,6 +31,50 @@ impl TryFrom<&HeaderValue> for ExternalToken {
     }
 }
 
+impl ResponseError for RepositoryError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RepositoryError::NotFound => StatusCode::NOT_FOUND,
+            RepositoryError::Conflict(_) => StatusCode::CONFLICT,
+            RepositoryError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            RepositoryError::Invalid(_) => StatusCode::BAD_REQUEST,
+            RepositoryError::Backend(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let detail = match self {
+            RepositoryError::Backend(e) => {
+                // Backend details are logged, but not exposed to the client
+                error!("Repository backend error: {:?}", e);
+                "The storage backend is unavailable".to_string()
+            }
+            other => other.to_string(),
+        };
+        ProblemDetails::new(self.status_code(), detail).into()
+    }
+}
+
+impl ResponseError for AuthorizationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthorizationError::Unauthenticated(_) => StatusCode::UNAUTHORIZED,
+            AuthorizationError::Forbidden(_) => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response: HttpResponse =
+            ProblemDetails::new(self.status_code(), self.to_string()).into();
+        if let AuthorizationError::Unauthenticated(_) = self {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -50,9 +97,21 @@ mod tests {
     fn test_parsing_invalid_token(#[case] token: &str) {
         let header = HeaderValue::from_str(token).unwrap();
         let token = ExternalToken::try_from(&header);
+        assert!(token.is_err_and(|e| e.to_string() == "Invalid token format"));
+    }
+
+    #[rstest]
+    #[case(RepositoryError::NotFound, StatusCode::NOT_FOUND)]
+    #[case(RepositoryError::Conflict("exists".to_string()), StatusCode::CONFLICT)]
+    #[case(RepositoryError::PreconditionFailed, StatusCode::PRECONDITION_FAILED)]
+    #[case(RepositoryError::Invalid("empty id".to_string()), StatusCode::BAD_REQUEST)]
+    #[case(RepositoryError::Backend(anyhow::anyhow!("timeout")), StatusCode::SERVICE_UNAVAILABLE)]
+    fn test_repository_error_response(#[case] error: RepositoryError, #[case] status: StatusCode) {
+        let response = error.error_response();
+        assert_eq!(response.status(), status);
         assert_eq!(
-            token.is_err_and(|e| e.to_string() == "Invalid token format"),
-            true
+            response.headers().get("content-type").unwrap(),
+            "application/problem+json"
         );
     }
 }
@@ -1,3 +1,7 @@
 /// This module contains functions references HTTP-related entities such as requests, responses, and routes.
 mod conversions;
+pub mod oauth_error;
+mod pagination;
+mod preconditions;
+pub mod problem_details;
 pub mod urls;
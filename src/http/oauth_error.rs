@@ -0,0 +1,109 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+/// Error response of the OAuth token endpoint as described in RFC 6749, section 5.2
+#[derive(Debug, Serialize)]
+pub struct OAuthError {
+    /// Error code, e.g. `invalid_request` or `invalid_grant`
+    pub error: &'static str,
+
+    /// Human-readable explanation of the error
+    pub error_description: String,
+}
+
+impl OAuthError {
+    /// The request is missing a parameter or has an unsupported parameter value
+    pub fn invalid_request(description: impl Into<String>) -> Self {
+        OAuthError::new("invalid_request", description)
+    }
+
+    /// The subject token is invalid, expired or cannot be exchanged
+    pub fn invalid_grant(description: impl Into<String>) -> Self {
+        OAuthError::new("invalid_grant", description)
+    }
+
+    /// The grant type is not supported by the token endpoint
+    pub fn unsupported_grant_type(description: impl Into<String>) -> Self {
+        OAuthError::new("unsupported_grant_type", description)
+    }
+
+    /// The requested scope is invalid or not allowed
+    pub fn invalid_scope(description: impl Into<String>) -> Self {
+        OAuthError::new("invalid_scope", description)
+    }
+
+    /// The requested audience is not allowed, as defined by RFC 8693
+    pub fn invalid_target(description: impl Into<String>) -> Self {
+        OAuthError::new("invalid_target", description)
+    }
+
+    /// The token could not be issued because of an internal failure, so the grant stays valid
+    pub fn server_error(description: impl Into<String>) -> Self {
+        OAuthError::new("server_error", description)
+    }
+
+    /// The storage is not available at the moment, so the request can be retried later with the same grant
+    pub fn temporarily_unavailable(description: impl Into<String>) -> Self {
+        OAuthError::new("temporarily_unavailable", description)
+    }
+
+    fn new(error: &'static str, description: impl Into<String>) -> Self {
+        OAuthError {
+            error,
+            error_description: description.into(),
+        }
+    }
+}
+
+impl Display for OAuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.error, self.error_description)
+    }
+}
+
+impl ResponseError for OAuthError {
+    fn status_code(&self) -> StatusCode {
+        match self.error {
+            "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+            "temporarily_unavailable" => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+            .json(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(OAuthError::invalid_grant("expired"), StatusCode::BAD_REQUEST)]
+    #[case(OAuthError::server_error("failed"), StatusCode::INTERNAL_SERVER_ERROR)]
+    #[case(
+        OAuthError::temporarily_unavailable("down"),
+        StatusCode::SERVICE_UNAVAILABLE
+    )]
+    fn test_status_code(#[case] error: OAuthError, #[case] expected: StatusCode) {
+        assert_eq!(error.status_code(), expected);
+    }
+
+    #[rstest]
+    fn test_error_response() {
+        let response = OAuthError::invalid_grant("expired").error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers().get("cache-control").unwrap(), "no-store");
+        assert_eq!(
+            serde_json::to_value(OAuthError::invalid_grant("expired")).unwrap(),
+            serde_json::json!({"error": "invalid_grant", "error_description": "expired"})
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use crate::models::external::claim_attachment::{
+    ClaimAttachmentKey, ClaimOperator, ClaimPredicate,
+};
+use crate::models::external::identity::ExternalIdentity;
+use crate::services::base::upsert_repository::{ListFilter, ListQuery, Page};
+use actix_web::error;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// The number of entities on a page when the `limit` parameter is missing
+const DEFAULT_LIMIT: usize = 100;
+
+/// The largest accepted `limit` parameter, larger limits are reduced to it
+const MAX_LIMIT: usize = 1000;
+
+/// Query parameters of the list routes
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListParams {
+    /// The `next_cursor` of the previous page
+    cursor: Option<String>,
+    limit: Option<usize>,
+    identity_provider: Option<String>,
+    prefix: Option<String>,
+    policy: Option<String>,
+    role: Option<String>,
+}
+
+impl ListParams {
+    /// Creates the repository query, rejecting the filters that the listed entities do not support
+    pub fn into_query<K: CursorKey>(self, filters: &[&str]) -> actix_web::Result<ListQuery<K>> {
+        let given = [
+            ("identity_provider", self.identity_provider.is_some()),
+            ("prefix", self.prefix.is_some()),
+            ("policy", self.policy.is_some()),
+            ("role", self.role.is_some()),
+        ];
+        if let Some((name, _)) = given
+            .iter()
+            .find(|(name, given)| *given && !filters.contains(name))
+        {
+            return Err(error::ErrorBadRequest(format!(
+                "Filter '{}' is not supported by this list",
+                name
+            )));
+        }
+        let after = match self.cursor {
+            Some(cursor) => Some(decode_cursor(&cursor)?),
+            None => None,
+        };
+        Ok(ListQuery {
+            after,
+            limit: self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+            filter: ListFilter {
+                identity_provider: self.identity_provider.map(|p| p.to_lowercase()),
+                prefix: self.prefix,
+                policy: self.policy,
+                role: self.role,
+            },
+        })
+    }
+}
+
+/// Keys of listed entities, converted to and from the opaque cursors of the following pages
+pub trait CursorKey: Sized {
+    fn to_parts(&self) -> Vec<String>;
+
+    fn from_parts(parts: Vec<String>) -> Option<Self>;
+}
+
+impl CursorKey for String {
+    fn to_parts(&self) -> Vec<String> {
+        vec![self.clone()]
+    }
+
+    fn from_parts(parts: Vec<String>) -> Option<Self> {
+        let [id]: [String; 1] = parts.try_into().ok()?;
+        Some(id)
+    }
+}
+
+impl CursorKey for (String, String) {
+    fn to_parts(&self) -> Vec<String> {
+        vec![self.0.clone(), self.1.clone()]
+    }
+
+    fn from_parts(parts: Vec<String>) -> Option<Self> {
+        let [identity_provider, user_id]: [String; 2] = parts.try_into().ok()?;
+        Some((identity_provider, user_id))
+    }
+}
+
+impl CursorKey for ExternalIdentity {
+    fn to_parts(&self) -> Vec<String> {
+        vec![self.identity_provider.clone(), self.user_id.clone()]
+    }
+
+    fn from_parts(parts: Vec<String>) -> Option<Self> {
+        <(String, String)>::from_parts(parts).map(ExternalIdentity::from)
+    }
+}
+
+impl CursorKey for ClaimAttachmentKey {
+    fn to_parts(&self) -> Vec<String> {
+        vec![
+            self.identity_provider.clone(),
+            self.predicate.claim.clone(),
+            self.predicate.operator.as_str().to_string(),
+            self.predicate.value.clone(),
+        ]
+    }
+
+    fn from_parts(parts: Vec<String>) -> Option<Self> {
+        let [identity_provider, claim, operator, value]: [String; 4] = parts.try_into().ok()?;
+        let predicate = ClaimPredicate {
+            claim,
+            operator: ClaimOperator::parse(&operator)?,
+            value,
+        };
+        Some(ClaimAttachmentKey::new(identity_provider, predicate))
+    }
+}
+
+/// Cursors are the url-safe base64 encoding of the JSON array of the key parts
+fn encode_cursor<K: CursorKey>(key: &K) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::Value::from(key.to_parts()).to_string())
+}
+
+fn decode_cursor<K: CursorKey>(cursor: &str) -> actix_web::Result<K> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .and_then(K::from_parts)
+        .ok_or_else(|| error::ErrorBadRequest("Invalid cursor"))
+}
+
+/// A page of a list route
+#[derive(Debug, Serialize)]
+pub struct ListResponse<T> {
+    pub items: Vec<T>,
+
+    /// The cursor of the next page, missing on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl<T> ListResponse<T> {
+    /// Creates the response from a page of the repository, with the items built from the keys and the entities
+    pub fn new<K: CursorKey, E>(page: Page<K, E>, item: impl Fn(K, E) -> T) -> Self {
+        let next_cursor = match page.more {
+            true => page.items.last().map(|(key, _)| encode_cursor(key)),
+            false => None,
+        };
+        ListResponse {
+            items: page
+                .items
+                .into_iter()
+                .map(|(key, entity)| item(key, entity))
+                .collect(),
+            next_cursor,
+        }
+    }
+}
+
+/// A listed entity with the fields of its key
+#[derive(Debug, Serialize)]
+pub struct ListItem<K, E> {
+    #[serde(flatten)]
+    pub key: K,
+
+    #[serde(flatten)]
+    pub entity: E,
+}
+
+/// The key of the entities identified by a single id, like policies and roles
+#[derive(Debug, Serialize)]
+pub struct EntityId {
+    pub id: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn params(cursor: Option<String>, limit: Option<usize>, policy: Option<&str>) -> ListParams {
+        ListParams {
+            cursor,
+            limit,
+            policy: policy.map(|p| p.to_string()),
+            ..ListParams::default()
+        }
+    }
+
+    #[rstest]
+    fn test_cursor_round_trip() {
+        let key = ClaimAttachmentKey::new(
+            "provider".to_string(),
+            ClaimPredicate {
+                claim: "groups".to_string(),
+                operator: ClaimOperator::Contains,
+                value: "data engineers/\"admins\"".to_string(),
+            },
+        );
+        let query: ListQuery<ClaimAttachmentKey> = params(Some(encode_cursor(&key)), None, None)
+            .into_query(&[])
+            .unwrap();
+        assert_eq!(query.after, Some(key));
+    }
+
+    #[rstest]
+    #[case("not a cursor")]
+    #[case("WyJhIl0")] // ["a"], a single part
+    #[case("WyJhIiwiYiIsImxpa2UiLCJkIl0")] // ["a","b","like","d"], an unknown operator
+    fn test_invalid_cursor(#[case] cursor: &str) {
+        let result: actix_web::Result<ListQuery<ClaimAttachmentKey>> =
+            params(Some(cursor.to_string()), None, None).into_query(&[]);
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    #[case(None, DEFAULT_LIMIT)]
+    #[case(Some(0), 1)]
+    #[case(Some(10), 10)]
+    #[case(Some(100_000), MAX_LIMIT)]
+    fn test_limit(#[case] limit: Option<usize>, #[case] expected: usize) {
+        let query: ListQuery<String> = params(None, limit, None).into_query(&[]).unwrap();
+        assert_eq!(query.limit, expected);
+    }
+
+    #[rstest]
+    #[case(&["policy", "role"], true)]
+    #[case(&["prefix"], false)]
+    fn test_supported_filters(#[case] filters: &[&str], #[case] accepted: bool) {
+        let result: actix_web::Result<ListQuery<String>> =
+            params(None, None, Some("reader")).into_query(filters);
+        assert_eq!(result.is_ok(), accepted);
+    }
+
+    #[rstest]
+    #[case(true, Some("WyJiIl0"))] // ["b"]
+    #[case(false, None)]
+    fn test_next_cursor(#[case] more: bool, #[case] expected: Option<&str>) {
+        let page = Page {
+            items: vec![("a".to_string(), 1), ("b".to_string(), 2)],
+            more,
+        };
+        let response = ListResponse::new(page, |id, _| EntityId { id });
+        assert_eq!(response.items.len(), 2);
+        assert_eq!(response.next_cursor.as_deref(), expected);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::services::base::upsert_repository::{Precondition, VersionMatch};
+use actix_web::http::header::{ETag, EntityTag, Header, IfMatch, IfNoneMatch};
+use actix_web::{error, HttpRequest};
+
+/// The `ETag` of a stored entity, the quoted version number
+pub fn etag(version: u64) -> ETag {
+    ETag(EntityTag::new_strong(version.to_string()))
+}
+
+/// Reads the precondition of a change from the `If-Match` and `If-None-Match` headers.
+/// Entity tags that are not quoted versions never match, and weak tags never match `If-Match`.
+pub fn precondition(req: &HttpRequest) -> actix_web::Result<Precondition> {
+    let mut precondition = Precondition::default();
+    if req.headers().contains_key(IfMatch::name()) {
+        precondition.if_match = match IfMatch::parse(req).map_err(error::ErrorBadRequest)? {
+            IfMatch::Any => Some(VersionMatch::Any),
+            IfMatch::Items(tags) => Some(versions(tags.iter().filter(|tag| !tag.weak))),
+        };
+    }
+    if req.headers().contains_key(IfNoneMatch::name()) {
+        precondition.if_none_match =
+            match IfNoneMatch::parse(req).map_err(error::ErrorBadRequest)? {
+                IfNoneMatch::Any => Some(VersionMatch::Any),
+                IfNoneMatch::Items(tags) => Some(versions(tags.iter())),
+            };
+    }
+    Ok(precondition)
+}
+
+fn versions<'a>(tags: impl Iterator<Item = &'a EntityTag>) -> VersionMatch {
+    VersionMatch::Versions(tags.filter_map(|tag| tag.tag().parse().ok()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{IF_MATCH, IF_NONE_MATCH};
+    use actix_web::test::TestRequest;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(None, None, Precondition::default())]
+    #[case(Some(r#""3""#), None, Precondition { if_match: Some(VersionMatch::Versions(vec![3])), if_none_match: None })]
+    #[case(Some(r#""2", "3""#), None, Precondition { if_match: Some(VersionMatch::Versions(vec![2, 3])), if_none_match: None })]
+    #[case(Some(r#"W/"3""#), None, Precondition { if_match: Some(VersionMatch::Versions(vec![])), if_none_match: None })]
+    #[case(Some(r#""abc""#), None, Precondition { if_match: Some(VersionMatch::Versions(vec![])), if_none_match: None })]
+    #[case(Some("3"), None, Precondition { if_match: Some(VersionMatch::Versions(vec![])), if_none_match: None })]
+    #[case(Some("*"), None, Precondition { if_match: Some(VersionMatch::Any), if_none_match: None })]
+    #[case(None, Some("*"), Precondition { if_match: None, if_none_match: Some(VersionMatch::Any) })]
+    #[case(None, Some(r#"W/"4""#), Precondition { if_match: None, if_none_match: Some(VersionMatch::Versions(vec![4])) })]
+    fn test_precondition(
+        #[case] if_match: Option<&str>,
+        #[case] if_none_match: Option<&str>,
+        #[case] expected: Precondition,
+    ) {
+        let mut request = TestRequest::default();
+        if let Some(value) = if_match {
+            request = request.insert_header((IF_MATCH, value));
+        }
+        if let Some(value) = if_none_match {
+            request = request.insert_header((IF_NONE_MATCH, value));
+        }
+        assert_eq!(precondition(&request.to_http_request()).unwrap(), expected);
+    }
+}
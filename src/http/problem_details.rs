@@ -0,0 +1,45 @@
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+/// Error response body as described in RFC 7807
+#[derive(Debug, Serialize)]
+pub struct ProblemDetails {
+    /// URI reference that identifies the problem type
+    #[serde(rename = "type")]
+    pub problem_type: String,
+
+    /// Short, human-readable summary of the problem type
+    pub title: String,
+
+    /// HTTP status code
+    pub status: u16,
+
+    /// Human-readable explanation specific to this occurrence of the problem
+    pub detail: String,
+}
+
+impl ProblemDetails {
+    /// Creates a problem with the default `about:blank` type, titled by the status code
+    pub fn new(status: StatusCode, detail: String) -> Self {
+        ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: status
+                .canonical_reason()
+                .unwrap_or("Unknown Error")
+                .to_string(),
+            status: status.as_u16(),
+            detail,
+        }
+    }
+}
+
+impl From<ProblemDetails> for HttpResponse {
+    fn from(value: ProblemDetails) -> Self {
+        let status =
+            StatusCode::from_u16(value.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .json(value)
+    }
+}
@@ -1,18 +1,51 @@
-use crate::models::external::identity::{ExternalIdentity, Policy, PolicyAttachment};
+use crate::http::oauth_error::OAuthError;
+use crate::http::pagination::{EntityId, ListItem, ListParams, ListResponse};
+use crate::http::preconditions::{etag, precondition};
+use crate::models::external::claim_attachment::{
+    ClaimAttachmentKey, ClaimOperator, ClaimPredicate,
+};
+use crate::models::external::identity::{ExternalIdentity, Policy, PolicyAttachment, Role};
 use crate::models::external::identity_provider::ExternalIdentityProvider;
+use crate::models::external::policy_revision::{self, PolicyChange};
 use crate::models::external::token::ExternalToken;
+use crate::models::internal::admin_settings::{AdminAction, AdminResource};
+use crate::models::internal::evaluation::EvaluationRequest;
+use crate::models::internal::openid_configuration::OpenIdConfiguration;
+use crate::models::internal::revocation::{Revocation, RevocationList, RevocationTarget};
+use crate::models::internal::token_settings::TokenSettings;
+use crate::models::internal::v1::token_exchange::{
+    requested_policies, TokenQuery, TokenRequest, TokenResponse, REFRESH_TOKEN_GRANT_TYPE,
+    REQUESTED_TOKEN_TYPES, SUBJECT_TOKEN_TYPES, TOKEN_EXCHANGE_GRANT_TYPE,
+};
+use crate::services::admin_authorizer::{
+    AdminAuthorizationService, AdminAuthorizer, AuthorizationError,
+};
+use crate::services::base::claim_attachment_store::ClaimAttachmentRepository;
+use crate::services::base::expiring_repository::RevocationRepository;
+use crate::services::base::policy_revision_store::PolicyRepository;
 use crate::services::base::upsert_repository::{
-    IdentityRepository, PolicyAttachmentRepository, PolicyRepository,
+    IdentityRepository, PolicyAttachmentRepository, RepositoryError, RoleRepository, Versioned,
+};
+use crate::services::policy_evaluator::{PolicyEvaluationService, PolicyEvaluator};
+use crate::services::policy_validator::{CedarPolicyValidator, PolicyValidator};
+use crate::services::signing_key_ring::{SigningKeyProvider, SigningKeyRing};
+use crate::services::token_introspector::{TokenIntrospectionService, TokenIntrospector};
+use crate::services::token_service::{
+    InvalidGrant, IssuedToken, PolicyNotAttached, TokenProvider, TokenService,
 };
-use crate::services::token_service::{TokenProvider, TokenService};
+use actix_web::http::header::{CacheControl, CacheDirective};
 use actix_web::{delete, error, get, post, web, HttpRequest, HttpResponse, Responder};
+use jsonwebtoken::jwk::JwkSet;
 use log::error;
+use serde::Deserialize;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[get("/token/{identity_provider}")]
 pub async fn token(
     data: web::Data<Arc<TokenService>>,
     identity_provider: web::Path<String>,
+    query: web::Query<TokenQuery>,
     req: HttpRequest,
 ) -> actix_web::Result<String> {
     let ip = ExternalIdentityProvider::from(identity_provider.to_string());
@@ -23,131 +56,1115 @@ pub async fn token(
                 error!("Error: {:?}", e);
                 error::ErrorUnauthorized("Invalid token format")
             })?;
-            data.issue_token(ip, token).await.map_err(|e| {
+            let policies = requested_policies(query.scope.as_deref());
+            let issued = data
+                .issue_token(ip, token, policies, false)
+                .await
+                .map_err(|e| match e.downcast_ref::<PolicyNotAttached>() {
+                    Some(not_attached) => error::ErrorForbidden(not_attached.to_string()),
+                    None => {
+                        error!("Error: {:?}", e);
+                        error::ErrorUnauthorized("Internal Server Error")
+                    }
+                })?;
+            Ok(issued.token)
+        }
+        None => Err(error::ErrorUnauthorized("No Authorization header found")),
+    }
+}
+
+#[get("/token/{identity_provider}/explain")]
+pub async fn explain_token(
+    data: web::Data<Arc<TokenService>>,
+    identity_provider: web::Path<String>,
+    query: web::Query<TokenQuery>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    let ip = ExternalIdentityProvider::from(identity_provider.to_string());
+    let Some(header) = req.headers().get("Authorization") else {
+        return Err(error::ErrorUnauthorized("No Authorization header found"));
+    };
+    let external_token = ExternalToken::try_from(header).map_err(|e| {
+        error!("Error: {:?}", e);
+        error::ErrorUnauthorized("Invalid token format")
+    })?;
+    let policies = requested_policies(query.scope.as_deref());
+    let explanation = data
+        .explain_token(ip, external_token, policies)
+        .await
+        .map_err(|e| match e.downcast_ref::<PolicyNotAttached>() {
+            Some(not_attached) => error::ErrorForbidden(not_attached.to_string()),
+            None => {
                 error!("Error: {:?}", e);
                 error::ErrorUnauthorized("Internal Server Error")
-            })
+            }
+        })?;
+    Ok(web::Json(explanation))
+}
+
+#[post("/oauth2/token")]
+pub async fn oauth2_token(
+    form: web::Form<TokenRequest>,
+    data: web::Data<Arc<TokenService>>,
+) -> Result<HttpResponse, OAuthError> {
+    let request = form.into_inner();
+    let issued = match request.grant_type.as_str() {
+        TOKEN_EXCHANGE_GRANT_TYPE => exchange_token(request, &data).await?,
+        REFRESH_TOKEN_GRANT_TYPE => refresh_token(request, &data).await?,
+        other => {
+            return Err(OAuthError::unsupported_grant_type(format!(
+                "Unsupported grant_type {}",
+                other
+            )))
+        }
+    };
+    let expires_in = issued
+        .expires_at
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(TokenResponse::new(
+            issued.token,
+            expires_in.as_secs(),
+            issued.refresh_token,
+        )))
+}
+
+/// Issues a token for the subject token of the RFC 8693 token exchange request
+async fn exchange_token(
+    request: TokenRequest,
+    data: &TokenService,
+) -> Result<IssuedToken, OAuthError> {
+    let (Some(subject_token), Some(subject_token_type)) =
+        (request.subject_token, request.subject_token_type)
+    else {
+        return Err(OAuthError::invalid_request(
+            "subject_token and subject_token_type are required",
+        ));
+    };
+    if !SUBJECT_TOKEN_TYPES.contains(&subject_token_type.as_str()) {
+        return Err(OAuthError::invalid_request(format!(
+            "Unsupported subject_token_type {}",
+            subject_token_type
+        )));
+    }
+    if let Some(requested) = &request.requested_token_type {
+        if !REQUESTED_TOKEN_TYPES.contains(&requested.as_str()) {
+            return Err(OAuthError::invalid_request(format!(
+                "Unsupported requested_token_type {}",
+                requested
+            )));
+        }
+    }
+
+    let subject_token = ExternalToken::from(subject_token);
+    let provider = data
+        .find_identity_provider(&subject_token)
+        .await
+        .map_err(issuance_error)?;
+    if let Some(audience) = &request.audience {
+        let settings = data
+            .get_token_settings(provider.clone())
+            .await
+            .map_err(issuance_error)?;
+        if &settings.audience != audience {
+            return Err(OAuthError::invalid_target(format!(
+                "Tokens of {} cannot be exchanged for audience {}",
+                provider.name(),
+                audience
+            )));
         }
-        None => Err(error::ErrorUnauthorized("No Authorization header found")),
     }
+
+    let policies = requested_policies(request.scope.as_deref());
+    data.issue_token(provider, subject_token, policies, true)
+        .await
+        .map_err(issuance_error)
+}
+
+/// Issues a token for the refresh token of the RFC 6749 refresh request
+async fn refresh_token(
+    request: TokenRequest,
+    data: &TokenService,
+) -> Result<IssuedToken, OAuthError> {
+    let Some(refresh_token) = request.refresh_token else {
+        return Err(OAuthError::invalid_request("refresh_token is required"));
+    };
+    let policies = requested_policies(request.scope.as_deref());
+    data.refresh_token(refresh_token, policies)
+        .await
+        .map_err(issuance_error)
+}
+
+/// Reports requests for policies that are not attached as `invalid_scope` and invalid grants as `invalid_grant`.
+/// Any other failure is internal and reported as a server error, so that the client keeps a grant that is still valid.
+fn issuance_error(e: anyhow::Error) -> OAuthError {
+    if let Some(not_attached) = e.downcast_ref::<PolicyNotAttached>() {
+        return OAuthError::invalid_scope(not_attached.to_string());
+    }
+    if let Some(invalid) = e.downcast_ref::<InvalidGrant>() {
+        return OAuthError::invalid_grant(invalid.to_string());
+    }
+    error!("Error: {:?}", e);
+    match e.downcast_ref::<RepositoryError>() {
+        Some(RepositoryError::Backend(_)) => {
+            OAuthError::temporarily_unavailable("Storage backend is not available")
+        }
+        _ => OAuthError::server_error("Token could not be issued"),
+    }
+}
+
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(data: web::Data<Arc<SigningKeyRing>>) -> impl Responder {
+    let keys = data.get_verification_keys().await;
+    web::Json(JwkSet {
+        keys: keys.iter().map(|key| key.to_jwk()).collect(),
+    })
+}
+
+/// The URLs are built from the configured public URL, as the `Host` and `X-Forwarded-*` headers are set by the client
+#[get("/.well-known/openid-configuration")]
+pub async fn openid_configuration(
+    data: web::Data<Arc<SigningKeyRing>>,
+    token_settings: web::Data<TokenSettings>,
+) -> impl Responder {
+    let keys = data.get_verification_keys().await;
+    web::Json(OpenIdConfiguration::new(
+        token_settings.issuer(),
+        token_settings.jwks_uri(),
+        keys.iter().map(|key| key.algorithm),
+    ))
+}
+
+/// Authenticates the admin API caller and checks that the caller is allowed to perform the action.
+async fn authorize(
+    req: &HttpRequest,
+    authorizer: &AdminAuthorizationService,
+    resource: AdminResource,
+    action: AdminAction,
+) -> Result<ExternalIdentity, AuthorizationError> {
+    let header = req.headers().get("Authorization").ok_or_else(|| {
+        AuthorizationError::Unauthenticated("No Authorization header found".to_string())
+    })?;
+    let external_token = ExternalToken::try_from(header)
+        .map_err(|_| AuthorizationError::Unauthenticated("Invalid token format".to_string()))?;
+    authorizer.authorize(external_token, resource, action).await
+}
+
+/// Query parameters of `POST /policy/{id}`
+#[derive(Deserialize)]
+pub struct PolicyQuery {
+    /// Stores the policy as a template with variables filled for each caller
+    #[serde(default)]
+    template: bool,
+
+    /// The reason for the change, recorded in the policy revision
+    reason: Option<String>,
+}
+
+/// Query parameters of `POST /policy/{id}/rollback/{revision}`
+#[derive(Deserialize)]
+pub struct RollbackQuery {
+    /// The reason for the rollback, recorded in the new policy revision
+    reason: Option<String>,
+}
+
+/// Query parameters of the requests attaching a policy
+#[derive(Deserialize)]
+pub struct AttachmentQuery {
+    /// Pins the attachment to a revision of the policy
+    revision: Option<u64>,
+}
+
+/// Records the admin API caller as the author of a policy change
+fn policy_change(caller: &ExternalIdentity, reason: Option<String>) -> PolicyChange {
+    PolicyChange::new(
+        format!("{}:{}", caller.identity_provider, caller.user_id),
+        reason,
+    )
+}
+
+/// Response header of `GET /policy/{id}` set for the policy templates
+const POLICY_TEMPLATE_HEADER: &str = "Boxer-Policy-Template";
+
+#[get("/policy")]
+pub async fn list_policies(
+    params: web::Query<ListParams>,
+    data: web::Data<Arc<PolicyRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(&req, &authorizer, AdminResource::Policy, AdminAction::Read).await?;
+    let query = params.into_inner().into_query(&["prefix"])?;
+    let page = data.list(query).await?;
+    Ok(web::Json(ListResponse::new(page, |id, policy| ListItem {
+        key: EntityId { id },
+        entity: policy,
+    })))
 }
 
 #[post("/policy/{id}")]
 pub async fn post_policy(
     id: web::Path<String>,
+    query: web::Query<PolicyQuery>,
     policy: String,
     data: web::Data<Arc<PolicyRepository>>,
+    validator: web::Data<Arc<CedarPolicyValidator>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
 ) -> actix_web::Result<HttpResponse> {
-    data.upsert(id.to_string(), Policy::new(policy))
-        .await
-        .map_err(|e| {
-            error!("Error: {:?}", e);
-            error::ErrorInternalServerError("Failed to upsert policy")
-        })?;
-    Ok(HttpResponse::Ok().finish())
+    let caller = authorize(&req, &authorizer, AdminResource::Policy, AdminAction::Write).await?;
+    let query = query.into_inner();
+    let policy = match query.template {
+        true => Policy::template(policy),
+        false => Policy::new(policy),
+    };
+    validator.validate(&policy)?;
+    let revision = data
+        .upsert_revision(
+            &id,
+            policy,
+            policy_change(&caller, query.reason),
+            precondition(&req)?,
+        )
+        .await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(revision.revision))
+        .json(revision))
 }
 
 #[get("/policy/{id}")]
 pub async fn get_policy(
     id: web::Path<String>,
     data: web::Data<Arc<PolicyRepository>>,
-) -> actix_web::Result<String> {
-    let policy = data.get(id.to_string()).await.map_err(|e| {
-        error!("Error: {:?}", e);
-        error::ErrorInternalServerError("Failed to upsert policy")
-    })?;
-    Ok(policy.content)
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(&req, &authorizer, AdminResource::Policy, AdminAction::Read).await?;
+    let Versioned {
+        entity: policy,
+        version,
+    } = data.get_versioned(id.to_string()).await?;
+    let mut response = HttpResponse::Ok();
+    response.insert_header(etag(version));
+    if policy.template {
+        response.insert_header((POLICY_TEMPLATE_HEADER, "true"));
+    }
+    Ok(response.body(policy.content))
 }
 
 #[delete("/policy/{id}")]
 pub async fn delete_policy(
     id: web::Path<String>,
     data: web::Data<Arc<PolicyRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
 ) -> actix_web::Result<HttpResponse> {
-    data.delete(id.to_string()).await.map_err(|e| {
-        error!("Error: {:?}", e);
-        error::ErrorInternalServerError("Failed to upsert policy")
-    })?;
+    authorize(&req, &authorizer, AdminResource::Policy, AdminAction::Write).await?;
+    data.delete_if(id.to_string(), precondition(&req)?).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
+#[get("/policy/{id}/revisions")]
+pub async fn policy_revisions(
+    id: web::Path<String>,
+    data: web::Data<Arc<PolicyRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(&req, &authorizer, AdminResource::Policy, AdminAction::Read).await?;
+    let revisions = data.list_revisions(&id).await?;
+    Ok(web::Json(revisions))
+}
+
+#[get("/policy/{id}/revisions/{revision}")]
+pub async fn get_policy_revision(
+    params: web::Path<(String, u64)>,
+    data: web::Data<Arc<PolicyRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(&req, &authorizer, AdminResource::Policy, AdminAction::Read).await?;
+    let (id, revision) = params.into_inner();
+    let revision = data.get_revision(&id, revision).await?;
+    Ok(web::Json(revision))
+}
+
+#[get("/policy/{id}/diff/{from}/{to}")]
+pub async fn diff_policy_revisions(
+    params: web::Path<(String, u64, u64)>,
+    data: web::Data<Arc<PolicyRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<String> {
+    authorize(&req, &authorizer, AdminResource::Policy, AdminAction::Read).await?;
+    let (id, from, to) = params.into_inner();
+    let from = data.get_revision(&id, from).await?;
+    let to = data.get_revision(&id, to).await?;
+    Ok(policy_revision::diff(&from, &to))
+}
+
+#[post("/policy/{id}/rollback/{revision}")]
+pub async fn rollback_policy(
+    params: web::Path<(String, u64)>,
+    query: web::Query<RollbackQuery>,
+    data: web::Data<Arc<PolicyRepository>>,
+    validator: web::Data<Arc<CedarPolicyValidator>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let caller = authorize(&req, &authorizer, AdminResource::Policy, AdminAction::Write).await?;
+    let (id, revision) = params.into_inner();
+    let target = data.get_revision(&id, revision).await?;
+    // The schema may have changed since the revision was stored
+    validator.validate(&target.policy)?;
+    let reason = query
+        .into_inner()
+        .reason
+        .unwrap_or_else(|| format!("Rollback to revision {}", revision));
+    let revision = data
+        .upsert_revision(
+            &id,
+            target.policy,
+            policy_change(&caller, Some(reason)),
+            precondition(&req)?,
+        )
+        .await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(revision.revision))
+        .json(revision))
+}
+
+#[get("/role")]
+pub async fn list_roles(
+    params: web::Query<ListParams>,
+    data: web::Data<Arc<RoleRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(&req, &authorizer, AdminResource::Role, AdminAction::Read).await?;
+    let query = params.into_inner().into_query(&["prefix"])?;
+    let page = data.list(query).await?;
+    Ok(web::Json(ListResponse::new(page, |id, role| ListItem {
+        key: EntityId { id },
+        entity: role,
+    })))
+}
+
+#[post("/role/{id}")]
+pub async fn post_role(
+    id: web::Path<String>,
+    role: web::Json<Role>,
+    data: web::Data<Arc<RoleRepository>>,
+    policies: web::Data<Arc<PolicyRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(&req, &authorizer, AdminResource::Role, AdminAction::Write).await?;
+    let mut missing = Vec::new();
+    for policy_id in &role.policies {
+        match policies.get(policy_id.clone()).await {
+            Ok(_) => {}
+            Err(RepositoryError::NotFound) => missing.push(policy_id.clone()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if !missing.is_empty() {
+        missing.sort();
+        return Err(RepositoryError::Invalid(format!(
+            "Policies do not exist: {}",
+            missing.join(", ")
+        ))
+        .into());
+    }
+    let version = data
+        .upsert_if(id.to_string(), role.into_inner(), precondition(&req)?)
+        .await?;
+    Ok(HttpResponse::Ok().insert_header(etag(version)).finish())
+}
+
+#[get("/role/{id}")]
+pub async fn get_role(
+    id: web::Path<String>,
+    data: web::Data<Arc<RoleRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(&req, &authorizer, AdminResource::Role, AdminAction::Read).await?;
+    let role = data.get_versioned(id.to_string()).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(role.version))
+        .json(role.entity))
+}
+
+#[delete("/role/{id}")]
+pub async fn delete_role(
+    id: web::Path<String>,
+    data: web::Data<Arc<RoleRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(&req, &authorizer, AdminResource::Role, AdminAction::Write).await?;
+    data.delete_if(id.to_string(), precondition(&req)?).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/identity")]
+pub async fn list_identities(
+    params: web::Query<ListParams>,
+    data: web::Data<Arc<IdentityRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Identity,
+        AdminAction::Read,
+    )
+    .await?;
+    let mut query = params
+        .into_inner()
+        .into_query(&["identity_provider", "prefix"])?;
+    // User ids are stored in lower case, like in external identities
+    query.filter.prefix = query.filter.prefix.map(|p| p.to_lowercase());
+    let page = data.list(query).await?;
+    Ok(web::Json(ListResponse::new(page, |_, identity| identity)))
+}
+
 #[post("/identity/{identity_provider}/{id}")]
 pub async fn post_identity(
     params: web::Path<(String, String)>,
     data: web::Data<Arc<IdentityRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
 ) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Identity,
+        AdminAction::Write,
+    )
+    .await?;
     let key = params.into_inner();
     let eid = ExternalIdentity::from(key.clone());
-    data.upsert(key, eid).await.map_err(|e| {
-        error!("Error: {:?}", e);
-        error::ErrorInternalServerError("Failed to upsert policy")
-    })?;
-    Ok(HttpResponse::Ok().finish())
+    let version = data.upsert_if(key, eid, precondition(&req)?).await?;
+    Ok(HttpResponse::Ok().insert_header(etag(version)).finish())
 }
 
 #[get("/identity/{identity_provider}/{id}")]
 pub async fn get_identity(
     params: web::Path<(String, String)>,
     data: web::Data<Arc<IdentityRepository>>,
-) -> actix_web::Result<impl Responder> {
-    let eid = data.get(params.into_inner()).await.map_err(|e| {
-        error!("Error: {:?}", e);
-        error::ErrorInternalServerError("Failed to upsert policy")
-    })?;
-    Ok(web::Json(eid))
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Identity,
+        AdminAction::Read,
+    )
+    .await?;
+    let eid = data.get_versioned(params.into_inner()).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(eid.version))
+        .json(eid.entity))
 }
 
 #[delete("/identity/{identity_provider}/{id}")]
 pub async fn delete_identity(
     params: web::Path<(String, String)>,
     data: web::Data<Arc<IdentityRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
 ) -> actix_web::Result<HttpResponse> {
-    data.delete(params.into_inner()).await.map_err(|e| {
-        error!("Error: {:?}", e);
-        error::ErrorInternalServerError("Failed to upsert policy")
-    })?;
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Identity,
+        AdminAction::Write,
+    )
+    .await?;
+    data.delete_if(params.into_inner(), precondition(&req)?)
+        .await?;
     Ok(HttpResponse::Ok().finish())
 }
 
+#[get("/attachment")]
+pub async fn list_policy_attachments(
+    params: web::Query<ListParams>,
+    data: web::Data<Arc<PolicyAttachmentRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Read,
+    )
+    .await?;
+    let mut query =
+        params
+            .into_inner()
+            .into_query(&["identity_provider", "prefix", "policy", "role"])?;
+    // User ids are stored in lower case, like in external identities
+    query.filter.prefix = query.filter.prefix.map(|p| p.to_lowercase());
+    let page = data.list(query).await?;
+    Ok(web::Json(ListResponse::new(
+        page,
+        |identity, attachment| ListItem {
+            key: identity,
+            entity: attachment,
+        },
+    )))
+}
+
 #[post("/attachment/{identity_provider}/{id}/{policy_id}")]
 pub async fn post_policy_attachment(
     params: web::Path<(String, String, String)>,
+    query: web::Query<AttachmentQuery>,
     data: web::Data<Arc<PolicyAttachmentRepository>>,
+    policies: web::Data<Arc<PolicyRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
 ) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Write,
+    )
+    .await?;
     let (identity_provider, id, policy_id) = params.into_inner();
-    let eid = ExternalIdentity::new(id, identity_provider);
-    let attachment = PolicyAttachment::single(policy_id);
-    data.upsert(eid, attachment).await.map_err(|e| {
-        error!("Error: {:?}", e);
-        error::ErrorInternalServerError("Failed to upsert policy")
-    })?;
-    Ok(HttpResponse::Ok().finish())
+    if let Some(revision) = query.revision {
+        policies.get_revision(&policy_id, revision).await?;
+    }
+    let eid = ExternalIdentity::new(identity_provider, id);
+    let attachment = PolicyAttachment::pinned(policy_id, query.revision);
+    let version = data.upsert_if(eid, attachment, precondition(&req)?).await?;
+    Ok(HttpResponse::Ok().insert_header(etag(version)).finish())
+}
+
+#[post("/attachment/{identity_provider}/{id}/role/{role_id}")]
+pub async fn post_role_attachment(
+    params: web::Path<(String, String, String)>,
+    data: web::Data<Arc<PolicyAttachmentRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Write,
+    )
+    .await?;
+    let (identity_provider, id, role_id) = params.into_inner();
+    let eid = ExternalIdentity::new(identity_provider, id);
+    let version = data
+        .upsert_if(eid, PolicyAttachment::role(role_id), precondition(&req)?)
+        .await?;
+    Ok(HttpResponse::Ok().insert_header(etag(version)).finish())
 }
 
-#[post("/attachment/{identity_provider}/{id}")]
+#[get("/attachment/{identity_provider}/{id}")]
 pub async fn get_policy_attachment(
     params: web::Path<(String, String)>,
     data: web::Data<Arc<PolicyAttachmentRepository>>,
-) -> actix_web::Result<impl Responder> {
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Read,
+    )
+    .await?;
     let (identity_provider, id) = params.into_inner();
-    let eid = ExternalIdentity::new(id, identity_provider);
-    let result = data.get(eid).await.map_err(|e| {
-        error!("Error: {:?}", e);
-        error::ErrorInternalServerError("Failed to upsert policy")
-    })?;
-    Ok(web::Json(result))
+    let eid = ExternalIdentity::new(identity_provider, id);
+    let result = data.get_versioned(eid).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(result.version))
+        .json(result.entity))
 }
 
 #[delete("/attachment/{identity_provider}/{id}")]
 pub async fn delete_policy_attachment(
     params: web::Path<(String, String)>,
     data: web::Data<Arc<PolicyAttachmentRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
 ) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Write,
+    )
+    .await?;
     let (identity_provider, id) = params.into_inner();
-    let eid = ExternalIdentity::new(id, identity_provider);
-    data.delete(eid).await.map_err(|e| {
-        error!("Error: {:?}", e);
-        error::ErrorInternalServerError("Failed to upsert policy")
-    })?;
+    let eid = ExternalIdentity::new(identity_provider, id);
+    data.delete_if(eid, precondition(&req)?).await?;
     Ok(HttpResponse::Ok().finish())
 }
+
+#[post("/claim-attachment/{identity_provider}/{claim}/{operator}/{value}/{policy_id}")]
+pub async fn post_claim_attachment(
+    params: web::Path<(String, String, ClaimOperator, String, String)>,
+    query: web::Query<AttachmentQuery>,
+    data: web::Data<Arc<ClaimAttachmentRepository>>,
+    policies: web::Data<Arc<PolicyRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Write,
+    )
+    .await?;
+    let (identity_provider, claim, operator, value, policy_id) = params.into_inner();
+    if let Some(revision) = query.revision {
+        policies.get_revision(&policy_id, revision).await?;
+    }
+    let key = claim_attachment_key(identity_provider, claim, operator, value);
+    let version = data
+        .upsert_if(
+            key,
+            PolicyAttachment::pinned(policy_id, query.revision),
+            precondition(&req)?,
+        )
+        .await?;
+    Ok(HttpResponse::Ok().insert_header(etag(version)).finish())
+}
+
+#[post("/claim-attachment/{identity_provider}/{claim}/{operator}/{value}/role/{role_id}")]
+pub async fn post_claim_role_attachment(
+    params: web::Path<(String, String, ClaimOperator, String, String)>,
+    data: web::Data<Arc<ClaimAttachmentRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Write,
+    )
+    .await?;
+    let (identity_provider, claim, operator, value, role_id) = params.into_inner();
+    let key = claim_attachment_key(identity_provider, claim, operator, value);
+    let version = data
+        .upsert_if(key, PolicyAttachment::role(role_id), precondition(&req)?)
+        .await?;
+    Ok(HttpResponse::Ok().insert_header(etag(version)).finish())
+}
+
+#[get("/claim-attachment/{identity_provider}/{claim}/{operator}/{value}")]
+pub async fn get_claim_attachment(
+    params: web::Path<(String, String, ClaimOperator, String)>,
+    data: web::Data<Arc<ClaimAttachmentRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Read,
+    )
+    .await?;
+    let (identity_provider, claim, operator, value) = params.into_inner();
+    let key = claim_attachment_key(identity_provider, claim, operator, value);
+    let result = data.get_versioned(key).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(result.version))
+        .json(result.entity))
+}
+
+#[delete("/claim-attachment/{identity_provider}/{claim}/{operator}/{value}")]
+pub async fn delete_claim_attachment(
+    params: web::Path<(String, String, ClaimOperator, String)>,
+    data: web::Data<Arc<ClaimAttachmentRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Write,
+    )
+    .await?;
+    let (identity_provider, claim, operator, value) = params.into_inner();
+    let key = claim_attachment_key(identity_provider, claim, operator, value);
+    data.delete_if(key, precondition(&req)?).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/claim-attachment")]
+pub async fn list_claim_attachments(
+    params: web::Query<ListParams>,
+    data: web::Data<Arc<ClaimAttachmentRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Attachment,
+        AdminAction::Read,
+    )
+    .await?;
+    let query = params
+        .into_inner()
+        .into_query(&["identity_provider", "policy", "role"])?;
+    let page = data.list(query).await?;
+    Ok(web::Json(ListResponse::new(page, |key, attachment| {
+        ListItem {
+            key,
+            entity: attachment,
+        }
+    })))
+}
+
+fn claim_attachment_key(
+    identity_provider: String,
+    claim: String,
+    operator: ClaimOperator,
+    value: String,
+) -> ClaimAttachmentKey {
+    ClaimAttachmentKey::new(
+        identity_provider,
+        ClaimPredicate {
+            claim,
+            operator,
+            value,
+        },
+    )
+}
+
+#[post("/revocation")]
+pub async fn post_revocation(
+    target: web::Json<RevocationTarget>,
+    data: web::Data<Arc<RevocationRepository>>,
+    token_settings: web::Data<TokenSettings>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Revocation,
+        AdminAction::Write,
+    )
+    .await?;
+    let revocation = Revocation::new(
+        target.into_inner(),
+        SystemTime::now(),
+        token_settings.revocation_lifetime(),
+    )
+    .map_err(error::ErrorInternalServerError)?;
+    data.insert(revocation.clone()).await?;
+    Ok(web::Json(revocation))
+}
+
+/// The revocations name the revoked users and policies, so they are only published to admin API callers
+#[get("/revocations")]
+pub async fn revocations(
+    data: web::Data<Arc<RevocationRepository>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Revocation,
+        AdminAction::Read,
+    )
+    .await?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(error::ErrorInternalServerError)?;
+    let revocations = data.list_active(now.as_secs()).await?;
+    Ok(web::Json(RevocationList { revocations }))
+}
+
+/// Form parameters of the token introspection request, as defined by RFC 7662
+#[derive(Deserialize)]
+pub struct IntrospectionRequest {
+    token: String,
+}
+
+#[post("/introspect")]
+pub async fn introspect(
+    form: web::Form<IntrospectionRequest>,
+    data: web::Data<Arc<TokenIntrospectionService>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Introspection,
+        AdminAction::Read,
+    )
+    .await?;
+    let result = data.introspect(&form.token).await?;
+    Ok(web::Json(result))
+}
+
+#[post("/evaluate")]
+pub async fn evaluate(
+    request: web::Json<EvaluationRequest>,
+    evaluator: web::Data<Arc<PolicyEvaluationService>>,
+    authorizer: web::Data<Arc<AdminAuthorizationService>>,
+    req: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    authorize(
+        &req,
+        &authorizer,
+        AdminResource::Evaluation,
+        AdminAction::Read,
+    )
+    .await?;
+    let response = evaluator.evaluate(request.into_inner()).await?;
+    Ok(web::Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::internal::admin_settings::AdminSettings;
+    use crate::models::internal::v1::token_exchange::JWT_TOKEN_TYPE;
+    use crate::services::signing_key_ring;
+    use crate::services::token_service::tests::{token_service, Fixture, PROVIDER};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use rstest::rstest;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio::sync::RwLock;
+
+    /// Authorizes the user of `PROVIDER` to perform the actions on the resource
+    fn admin_authorizer(
+        fixture: &Fixture,
+        resource: AdminResource,
+        actions: Vec<AdminAction>,
+    ) -> Arc<AdminAuthorizationService> {
+        Arc::new(AdminAuthorizationService::new(
+            fixture.validators.clone(),
+            Some(AdminSettings {
+                identity_provider: PROVIDER.to_string(),
+                users: HashMap::from([("user".to_string(), HashMap::from([(resource, actions)]))]),
+            }),
+        ))
+    }
+
+    #[rstest]
+    #[actix_web::test]
+    async fn test_token_does_not_store_refresh_tokens() {
+        let Fixture {
+            service,
+            refresh_tokens,
+            ..
+        } = token_service().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .service(token),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/token/{}", PROVIDER))
+            .insert_header(("Authorization", "Bearer external"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(refresh_tokens.read().await.is_empty());
+    }
+
+    #[rstest]
+    #[case(None, "https://boxer.sneaksanddata.com")]
+    #[case(Some("https://issuer.example.com"), "https://issuer.example.com")]
+    #[actix_web::test]
+    async fn test_openid_configuration(#[case] issuer: Option<&str>, #[case] expected: &str) {
+        let signing_keys = Arc::new(signing_key_ring::new(Duration::from_secs(3600)));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(signing_keys))
+                .app_data(web::Data::new(TokenSettings {
+                    issuer: issuer.map(|issuer| issuer.to_string()),
+                    ..TokenSettings::default()
+                }))
+                .service(openid_configuration),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/.well-known/openid-configuration")
+            .insert_header(("Host", "attacker.example.com"))
+            .insert_header(("X-Forwarded-Host", "attacker.example.com"))
+            .to_request();
+        let document: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(document["issuer"], expected);
+        assert_eq!(
+            document["jwks_uri"],
+            "https://boxer.sneaksanddata.com/.well-known/jwks.json"
+        );
+        assert_eq!(document["response_types_supported"][0], "id_token");
+        assert_eq!(document["subject_types_supported"][0], "public");
+    }
+
+    #[rstest]
+    #[case(anyhow::anyhow!(InvalidGrant("Refresh token is not valid")), "invalid_grant", "Refresh token is not valid")]
+    #[case(
+        anyhow::anyhow!(PolicyNotAttached(vec!["writer".to_string()])),
+        "invalid_scope",
+        "Policies are not attached: writer"
+    )]
+    #[case(
+        anyhow::anyhow!(RepositoryError::Backend(anyhow::anyhow!("connection refused"))),
+        "temporarily_unavailable",
+        "Storage backend is not available"
+    )]
+    #[case(anyhow::anyhow!("No active signing key"), "server_error", "Token could not be issued")]
+    #[actix_web::test]
+    async fn test_issuance_error(
+        #[case] e: anyhow::Error,
+        #[case] error: &str,
+        #[case] description: &str,
+    ) {
+        let result = issuance_error(e);
+        assert_eq!(result.error, error);
+        assert_eq!(result.error_description, description);
+    }
+
+    #[rstest]
+    #[actix_web::test]
+    async fn test_unknown_refresh_token_is_an_invalid_grant() {
+        let Fixture { service, .. } = token_service().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .service(oauth2_token),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/oauth2/token")
+            .set_form([
+                ("grant_type", REFRESH_TOKEN_GRANT_TYPE),
+                ("refresh_token", "unknown"),
+            ])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({"error": "invalid_grant", "error_description": "Refresh token is not valid"})
+        );
+    }
+
+    #[rstest]
+    #[actix_web::test]
+    async fn test_unreadable_subject_token_is_an_invalid_grant() {
+        let Fixture { service, .. } = token_service().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .service(oauth2_token),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/oauth2/token")
+            .set_form([
+                ("grant_type", TOKEN_EXCHANGE_GRANT_TYPE),
+                ("subject_token", "not a jwt"),
+                ("subject_token_type", JWT_TOKEN_TYPE),
+            ])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({"error": "invalid_grant", "error_description": "Subject token is not valid"})
+        );
+    }
+
+    #[rstest]
+    #[case(r#"{"policies": ["reader"]}"#, StatusCode::OK)]
+    #[case(r#"{"policies": ["reader", "unknown"]}"#, StatusCode::BAD_REQUEST)]
+    #[actix_web::test]
+    async fn test_roles_only_reference_existing_policies(
+        #[case] role: &str,
+        #[case] status: StatusCode,
+    ) {
+        let fixture = token_service().await;
+        let roles: Arc<RoleRepository> = Arc::new(RwLock::new(HashMap::new()));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(roles.clone()))
+                .app_data(web::Data::new(fixture.policies.clone()))
+                .app_data(web::Data::new(admin_authorizer(
+                    &fixture,
+                    AdminResource::Role,
+                    vec![AdminAction::Write],
+                )))
+                .service(post_role),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/role/readers")
+            .insert_header(("Authorization", "Bearer external"))
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(role.to_string())
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), status);
+        assert_eq!(
+            roles.get("readers".to_string()).await.is_ok(),
+            status == StatusCode::OK
+        );
+    }
+
+    #[rstest]
+    #[case(None, StatusCode::UNAUTHORIZED)]
+    #[case(Some(AdminAction::Write), StatusCode::FORBIDDEN)]
+    #[case(Some(AdminAction::Read), StatusCode::OK)]
+    #[actix_web::test]
+    async fn test_revocations_require_the_read_permission(
+        #[case] granted: Option<AdminAction>,
+        #[case] status: StatusCode,
+    ) {
+        let fixture = token_service().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(fixture.revocations.clone()))
+                .app_data(web::Data::new(admin_authorizer(
+                    &fixture,
+                    AdminResource::Revocation,
+                    granted.into_iter().collect(),
+                )))
+                .service(revocations),
+        )
+        .await;
+
+        let mut request = test::TestRequest::get().uri("/revocations");
+        if granted.is_some() {
+            request = request.insert_header(("Authorization", "Bearer external"));
+        }
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), status);
+    }
+}
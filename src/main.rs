@@ -3,69 +3,149 @@ mod models;
 mod services;
 
 use crate::http::urls::{
-    delete_identity, delete_policy, delete_policy_attachment, get_identity, get_policy,
-    get_policy_attachment, post_identity, post_policy, post_policy_attachment, token,
-};
-use crate::services::base::upsert_repository::{
-    IdentityRepository, PolicyAttachmentRepository, PolicyRepository,
+    delete_claim_attachment, delete_identity, delete_policy, delete_policy_attachment, delete_role,
+    diff_policy_revisions, evaluate, explain_token, get_claim_attachment, get_identity, get_policy,
+    get_policy_attachment, get_policy_revision, get_role, introspect, jwks, list_claim_attachments,
+    list_identities, list_policies, list_policy_attachments, list_roles, oauth2_token,
+    openid_configuration, policy_revisions, post_claim_attachment, post_claim_role_attachment,
+    post_identity, post_policy, post_policy_attachment, post_revocation, post_role,
+    post_role_attachment, revocations, rollback_policy, token,
 };
+use crate::services::admin_authorizer::AdminAuthorizationService;
 use crate::services::configuration_manager::ConfigurationManager;
 use crate::services::identity_validator_provider;
+use crate::services::identity_validator_provider::ExternalIdentityValidatorManager;
+use crate::services::policy_evaluator;
+use crate::services::policy_validator;
+use crate::services::repositories;
+use crate::services::signing_key_ring;
+use crate::services::signing_key_ring::SigningKeyManager;
+use crate::services::token_introspector;
 use crate::services::token_service::TokenService;
 use actix_web::{web, App, HttpServer};
 use log::info;
-use std::collections::HashMap;
-use std::io::Result;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 
 #[actix_web::main]
-async fn main() -> Result<()> {
+async fn main() -> anyhow::Result<()> {
     std::env::set_var("RUST_LOG", "debug");
     env_logger::init();
     let addr = ("127.0.0.1", 8080);
     let validator_provider = Arc::new(identity_validator_provider::new());
     let cm = Arc::clone(&validator_provider);
-    let secret = Arc::new(cm.get_signing_key());
+    let token_settings = cm.get_token_settings()?;
+    let signing_keys = Arc::new(signing_key_ring::new(token_settings.max_lifetime()));
+    signing_keys.update(cm.get_signing_keys()?).await?;
 
-    let _ = tokio::spawn(cm.watch_for_identity_providers());
-    info!("Configuration manager started");
+    // Providers are registered before the server starts, so the first requests can be validated
+    cm.update_identity_providers().await?;
+    info!(
+        "Registered {} identity providers",
+        validator_provider.get_settings().await.len()
+    );
+
+    let repositories = repositories::new(&cm.get_repository_settings()?).await?;
+    let policy_repository = repositories.policies;
+    let role_repository = repositories.roles;
+    let policy_attachments_repository = repositories.policy_attachments;
+    let claim_attachments_repository = repositories.claim_attachments;
+    let identity_repository = repositories.identities;
+    let revocation_repository = repositories.revocations;
+    let refresh_token_repository = repositories.refresh_tokens;
+
+    let token_introspector = Arc::new(token_introspector::new(
+        signing_keys.clone(),
+        revocation_repository.clone(),
+    ));
 
-    let policy_repository: Arc<PolicyRepository> = Arc::new(RwLock::new(HashMap::new()));
-    let policy_attachments_repository: Arc<PolicyAttachmentRepository> =
-        Arc::new(RwLock::new(HashMap::new()));
-    let identity_repository: Arc<IdentityRepository> = Arc::new(RwLock::new(HashMap::new()));
+    let policy_validator = Arc::new(policy_validator::new(&cm.get_policy_settings()?)?);
+
+    let admin_authorizer = Arc::new(AdminAuthorizationService::new(
+        validator_provider.clone(),
+        cm.get_admin_settings()?,
+    ));
+
+    tokio::spawn(cm.clone().watch_for_signing_keys(signing_keys.clone()));
+    tokio::spawn(cm.watch_for_identity_providers());
+    info!("Configuration manager started");
 
     info!("listening on {}:{}", &addr.0, &addr.1);
     HttpServer::new(move || {
         let token_provider = Arc::new(TokenService::new(
             validator_provider.clone(),
             policy_repository.clone(),
+            role_repository.clone(),
             policy_attachments_repository.clone(),
-            Arc::clone(&secret),
+            claim_attachments_repository.clone(),
+            Arc::clone(&signing_keys),
+            token_settings.clone(),
+            refresh_token_repository.clone(),
+            revocation_repository.clone(),
         ));
+        let policy_evaluator = Arc::new(policy_evaluator::new(token_provider.clone()));
         App::new()
             // Application services
             .app_data(web::Data::new(token_provider))
+            .app_data(web::Data::new(policy_evaluator))
             .app_data(web::Data::new(policy_repository.clone()))
+            .app_data(web::Data::new(role_repository.clone()))
+            .app_data(web::Data::new(policy_validator.clone()))
             .app_data(web::Data::new(policy_attachments_repository.clone()))
+            .app_data(web::Data::new(claim_attachments_repository.clone()))
             .app_data(web::Data::new(identity_repository.clone()))
+            .app_data(web::Data::new(revocation_repository.clone()))
+            .app_data(web::Data::new(signing_keys.clone()))
+            .app_data(web::Data::new(admin_authorizer.clone()))
+            .app_data(web::Data::new(token_introspector.clone()))
+            .app_data(web::Data::new(token_settings.clone()))
             // Token endpoint
             .service(token)
+            .service(explain_token)
+            .service(oauth2_token)
+            // Token verification keys
+            .service(jwks)
+            .service(openid_configuration)
             // Policy CRUD
+            .service(list_policies)
             .service(post_policy)
             .service(get_policy)
             .service(delete_policy)
+            .service(policy_revisions)
+            .service(get_policy_revision)
+            .service(diff_policy_revisions)
+            .service(rollback_policy)
+            // Role CRUD
+            .service(list_roles)
+            .service(post_role)
+            .service(get_role)
+            .service(delete_role)
             // Identity CRUD
+            .service(list_identities)
             .service(post_identity)
             .service(get_identity)
             .service(delete_identity)
             // Policy Attachment CRUD
+            .service(list_policy_attachments)
             .service(post_policy_attachment)
             .service(get_policy_attachment)
             .service(delete_policy_attachment)
+            .service(post_role_attachment)
+            // Claim Attachment CRUD
+            .service(post_claim_attachment)
+            .service(get_claim_attachment)
+            .service(delete_claim_attachment)
+            .service(post_claim_role_attachment)
+            .service(list_claim_attachments)
+            // Token revocation
+            .service(post_revocation)
+            .service(revocations)
+            // Token introspection
+            .service(introspect)
+            // Policy evaluation
+            .service(evaluate)
     })
     .bind(addr)?
     .run()
-    .await
+    .await?;
+    Ok(())
 }
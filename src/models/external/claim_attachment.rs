@@ -0,0 +1,147 @@
+use crate::models::external::identity::PolicyAttachment;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// How the value of a claim is compared with the value of the predicate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimOperator {
+    /// The claim is a single value equal to the predicate value
+    Equals,
+
+    /// The claim is an array, or a space-separated string, with an element equal to the predicate value
+    Contains,
+}
+
+impl ClaimOperator {
+    /// The name of the operator in the urls and in the database backends
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClaimOperator::Equals => "equals",
+            ClaimOperator::Contains => "contains",
+        }
+    }
+
+    /// Reads the operator from its name
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "equals" => Some(ClaimOperator::Equals),
+            "contains" => Some(ClaimOperator::Contains),
+            _ => None,
+        }
+    }
+}
+
+/// A predicate over a claim of a validated external token, e.g. `groups` contains `admins`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ClaimPredicate {
+    /// The name of the claim
+    pub claim: String,
+
+    /// How the claim is compared with the value
+    pub operator: ClaimOperator,
+
+    /// The expected value
+    pub value: String,
+}
+
+impl ClaimPredicate {
+    /// Checks whether the claims of the external token satisfy the predicate.
+    /// Strings are compared as is, numbers and booleans by their JSON representation.
+    pub fn matches(&self, claims: &HashMap<String, Value>) -> bool {
+        let Some(claim) = claims.get(&self.claim) else {
+            return false;
+        };
+        match (self.operator, claim) {
+            (ClaimOperator::Equals, value) => self.equals(value),
+            (ClaimOperator::Contains, Value::Array(values)) => {
+                values.iter().any(|v| self.equals(v))
+            }
+            (ClaimOperator::Contains, Value::String(value)) => {
+                value.split_whitespace().any(|v| v == self.value)
+            }
+            (ClaimOperator::Contains, _) => false,
+        }
+    }
+
+    fn equals(&self, value: &Value) -> bool {
+        match value {
+            Value::String(value) => *value == self.value,
+            Value::Number(value) => self.value == value.to_string(),
+            Value::Bool(value) => self.value == value.to_string(),
+            _ => false,
+        }
+    }
+}
+
+/// Identifies a claim attachment: the predicate is only evaluated for tokens of the identity provider
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct ClaimAttachmentKey {
+    /// The name of the external identity provider
+    pub identity_provider: String,
+
+    /// The predicate the validated token must satisfy
+    #[serde(flatten)]
+    pub predicate: ClaimPredicate,
+}
+
+impl ClaimAttachmentKey {
+    /// Creates a new key. Identity provider names are case-insensitive, like in external identities.
+    pub fn new(identity_provider: String, predicate: ClaimPredicate) -> Self {
+        ClaimAttachmentKey {
+            identity_provider: identity_provider.to_lowercase(),
+            predicate,
+        }
+    }
+}
+
+/// Policies and roles attached to every user of the identity provider whose token satisfies the predicate
+#[derive(Debug, Clone, Serialize)]
+pub struct ClaimAttachment {
+    #[serde(flatten)]
+    pub predicate: ClaimPredicate,
+
+    #[serde(flatten)]
+    pub attachment: PolicyAttachment,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[rstest]
+    #[case(ClaimOperator::Contains, "groups", "admins", true)]
+    #[case(ClaimOperator::Contains, "groups", "guests", false)]
+    #[case(ClaimOperator::Equals, "groups", "admins", false)]
+    #[case(ClaimOperator::Contains, "scp", "write", true)]
+    #[case(ClaimOperator::Equals, "tid", "tenant", true)]
+    #[case(ClaimOperator::Contains, "tid", "tenant", true)]
+    #[case(ClaimOperator::Equals, "tid", "Tenant", false)]
+    #[case(ClaimOperator::Equals, "level", "3", true)]
+    #[case(ClaimOperator::Equals, "verified", "true", true)]
+    #[case(ClaimOperator::Equals, "missing", "value", false)]
+    fn test_predicate_matches(
+        #[case] operator: ClaimOperator,
+        #[case] claim: &str,
+        #[case] value: &str,
+        #[case] expected: bool,
+    ) {
+        let claims: HashMap<String, Value> = serde_json::from_value(json!({
+            "groups": ["admins", "users"],
+            "scp": "read write",
+            "tid": "tenant",
+            "level": 3,
+            "verified": true,
+        }))
+        .unwrap();
+        let predicate = ClaimPredicate {
+            claim: claim.to_string(),
+            operator,
+            value: value.to_string(),
+        };
+        assert_eq!(predicate.matches(&claims), expected);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::models::external::policy_template;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize)]
 /// Struct that represents an external identity
@@ -27,45 +29,120 @@ impl From<(String, String)> for ExternalIdentity {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 #[allow(dead_code)]
 pub struct PolicyAttachment {
     pub policies: HashSet<String>,
+
+    /// Roles whose policies are attached, expanded when a token is issued
+    pub roles: HashSet<String>,
+
+    /// Revisions the attached policies are pinned to, by policy id. Other policies follow the current revision.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub revisions: HashMap<String, u64>,
 }
 
 #[allow(dead_code)]
 impl PolicyAttachment {
     pub fn new(policies: HashSet<String>) -> Self {
-        PolicyAttachment { policies }
+        PolicyAttachment {
+            policies,
+            roles: HashSet::new(),
+            revisions: HashMap::new(),
+        }
     }
 
     pub fn single(policy: String) -> Self {
         let mut set = HashSet::new();
         set.insert(policy);
-        PolicyAttachment { policies: set }
+        PolicyAttachment::new(set)
     }
+
+    /// Creates an attachment of a single policy, pinned to the revision if one is given
+    pub fn pinned(policy: String, revision: Option<u64>) -> Self {
+        let mut attachment = PolicyAttachment::single(policy.clone());
+        if let Some(revision) = revision {
+            attachment.revisions.insert(policy, revision);
+        }
+        attachment
+    }
+
+    /// Creates an attachment of a single role
+    pub fn role(role: String) -> Self {
+        PolicyAttachment {
+            policies: HashSet::new(),
+            roles: HashSet::from([role]),
+            revisions: HashMap::new(),
+        }
+    }
+
+    /// Adds the policies and roles of the other attachment.
+    /// The policies attached again are pinned like in the other attachment, or unpinned.
+    pub fn merge(&mut self, other: PolicyAttachment) {
+        for policy in &other.policies {
+            match other.revisions.get(policy) {
+                Some(revision) => self.revisions.insert(policy.clone(), *revision),
+                None => self.revisions.remove(policy),
+            };
+        }
+        self.policies.extend(other.policies);
+        self.roles.extend(other.roles);
+    }
+}
+
+/// A named bundle of policies that is attached to identities as a unit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Role {
+    pub policies: HashSet<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Policy {
     pub content: String,
+
+    /// Whether the content is a template with variables filled for each caller, see `policy_template`
+    #[serde(default)]
+    pub template: bool,
 }
 
 #[allow(dead_code)]
 impl Policy {
     pub fn new(content: String) -> Self {
-        Policy { content }
+        Policy {
+            content,
+            template: false,
+        }
     }
 
-    pub fn empty() -> Self {
+    /// Creates a policy template
+    pub fn template(content: String) -> Self {
         Policy {
-            content: String::new(),
+            content,
+            template: true,
         }
     }
 
+    pub fn empty() -> Self {
+        Policy::new(String::new())
+    }
+
     pub fn merge(&self, other: Policy) -> Self {
-        Policy {
-            content: format!("{}\n{}", self.content, other.content),
+        Policy::new(format!("{}\n{}", self.content, other.content))
+    }
+
+    /// Fills the template variables for the identity, or returns the policy as is if it is not a template
+    pub fn instantiate(
+        self,
+        identity: &ExternalIdentity,
+        claims: &HashMap<String, Value>,
+    ) -> Result<Policy, anyhow::Error> {
+        match self.template {
+            true => Ok(Policy::new(policy_template::instantiate(
+                &self.content,
+                identity,
+                claims,
+            )?)),
+            false => Ok(self),
         }
     }
 }
@@ -1,3 +1,8 @@
+use crate::models::internal::token_settings::TokenSettingsOverride;
+use anyhow::bail;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct OidcExternalIdentityProviderSettings {
     /// The claim that contains the user id (or name) in the external token.
     /// This is used to extract the user id from the token and issue the internal token with
@@ -13,4 +18,31 @@ pub struct OidcExternalIdentityProviderSettings {
 
     /// The list of audiences that are allowed to consume tokens.
     pub audiences: Vec<String>,
+
+    /// Overrides of the global settings for the internal tokens issued to the users of this provider.
+    #[serde(default)]
+    pub token: Option<TokenSettingsOverride>,
+}
+
+impl OidcExternalIdentityProviderSettings {
+    /// Checks that the settings can be used to build a validator.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.user_id_claim.trim().is_empty() {
+            bail!("user_id_claim must not be empty");
+        }
+        if !self.discovery_url.starts_with("https://") && !self.discovery_url.starts_with("http://")
+        {
+            bail!(
+                "discovery_url must be an http(s) URL, got '{}'",
+                self.discovery_url
+            );
+        }
+        if self.issuers.is_empty() {
+            bail!("issuers must not be empty");
+        }
+        if self.audiences.is_empty() {
+            bail!("audiences must not be empty");
+        }
+        Ok(())
+    }
 }
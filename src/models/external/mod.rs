@@ -1,4 +1,7 @@
+pub mod claim_attachment;
 pub mod identity;
 pub mod identity_provider;
 pub mod identity_provider_settings;
+pub mod policy_revision;
+pub mod policy_template;
 pub mod token;
@@ -0,0 +1,134 @@
+use crate::models::external::identity::Policy;
+use serde::Serialize;
+use similar::{Algorithm, TextDiff};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Who changes a policy and why
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicyChange {
+    /// The admin API caller that made the change
+    pub author: Option<String>,
+
+    /// The reason given for the change
+    pub reason: Option<String>,
+}
+
+impl PolicyChange {
+    /// Creates a change made by the author
+    pub fn new(author: String, reason: Option<String>) -> Self {
+        PolicyChange {
+            author: Some(author),
+            reason,
+        }
+    }
+}
+
+/// An immutable revision of a policy. Revisions are numbered from 1 for each policy id.
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyRevision {
+    pub revision: u64,
+
+    #[serde(flatten)]
+    pub policy: Policy,
+
+    pub author: Option<String>,
+    pub reason: Option<String>,
+
+    /// The time the revision was stored, in seconds since the Unix epoch
+    pub created_at: u64,
+}
+
+impl PolicyRevision {
+    /// Creates the revision of a change made now
+    pub fn new(revision: u64, policy: Policy, change: PolicyChange) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        PolicyRevision {
+            revision,
+            policy,
+            author: change.author,
+            reason: change.reason,
+            created_at,
+        }
+    }
+}
+
+/// Compares the contents of two revisions line by line, in the unified diff format with three lines of context.
+/// The diff is empty if the contents are equal.
+pub fn diff(from: &PolicyRevision, to: &PolicyRevision) -> String {
+    let old: Vec<&str> = from.policy.content.lines().collect();
+    let new: Vec<&str> = to.policy.content.lines().collect();
+
+    // Myers' algorithm runs in linear space, as the policies are not limited in size,
+    // and falls back to a coarser diff after the timeout if the revisions are very different
+    let lines = TextDiff::configure()
+        .algorithm(Algorithm::Myers)
+        .timeout(Duration::from_secs(1))
+        .diff_slices(&old, &new);
+
+    // The lines are compared without their line breaks, which are not part of the policy
+    lines
+        .unified_diff()
+        .context_radius(3)
+        .missing_newline_hint(false)
+        .header(
+            &format!("revision {}", from.revision),
+            &format!("revision {}", to.revision),
+        )
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn revision(revision: u64, content: &str) -> PolicyRevision {
+        PolicyRevision::new(
+            revision,
+            Policy::new(content.to_string()),
+            PolicyChange::default(),
+        )
+    }
+
+    #[rstest]
+    #[case("a\nb\nc", "a\nb\nc", "")]
+    #[case(
+        "a\nb\nc",
+        "a\nc",
+        "--- revision 1\n+++ revision 2\n@@ -1,3 +1,2 @@\n a\n-b\n c\n"
+    )]
+    #[case(
+        "a\nc",
+        "a\nb\nc\nd",
+        "--- revision 1\n+++ revision 2\n@@ -1,2 +1,4 @@\n a\n+b\n c\n+d\n"
+    )]
+    #[case("", "a", "--- revision 1\n+++ revision 2\n@@ -0,0 +1 @@\n+a\n")]
+    #[case(
+        "1\n2\n3\n4\n5\n6\n7\n8\n9\n10",
+        "1\n2\nthree\n4\n5\n6\n7\n8\nnine\n10",
+        "--- revision 1\n+++ revision 2\n@@ -1,10 +1,10 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n 7\n 8\n-9\n+nine\n 10\n"
+    )]
+    #[case(
+        "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12",
+        "one\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\ntwelve",
+        "--- revision 1\n+++ revision 2\n@@ -1,4 +1,4 @@\n-1\n+one\n 2\n 3\n 4\n@@ -9,4 +9,4 @@\n 9\n 10\n 11\n-12\n+twelve\n"
+    )]
+    fn test_diff(#[case] from: &str, #[case] to: &str, #[case] expected: &str) {
+        assert_eq!(diff(&revision(1, from), &revision(2, to)), expected);
+    }
+
+    #[rstest]
+    fn test_diff_of_large_policies() {
+        let from: Vec<String> = (0..100_000).map(|i| format!("line {}", i)).collect();
+        let mut to = from.clone();
+        to[50_000] = "changed".to_string();
+
+        let result = diff(&revision(1, &from.join("\n")), &revision(2, &to.join("\n")));
+        assert!(result.contains("@@ -49998,7 +49998,7 @@\n line 49997\n"));
+        assert!(result.contains("\n-line 50000\n+changed\n line 50001\n"));
+        assert_eq!(result.lines().count(), 11);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::models::external::identity::ExternalIdentity;
+use anyhow::{anyhow, bail};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A variable of a policy template, written as `{{user_id}}`, `{{identity_provider}}` or `{{claims.<name>}}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateVariable<'a> {
+    /// The user id of the caller
+    UserId,
+
+    /// The name of the identity provider that authenticated the caller
+    IdentityProvider,
+
+    /// A single-valued claim of the validated external token
+    Claim(&'a str),
+}
+
+impl<'a> TemplateVariable<'a> {
+    fn parse(name: &'a str) -> Result<Self, anyhow::Error> {
+        match name {
+            "user_id" => Ok(TemplateVariable::UserId),
+            "identity_provider" => Ok(TemplateVariable::IdentityProvider),
+            _ => match name.strip_prefix("claims.") {
+                Some(claim) if !claim.is_empty() => Ok(TemplateVariable::Claim(claim)),
+                _ => bail!("Unknown template variable '{}'", name),
+            },
+        }
+    }
+}
+
+/// Replaces the template variables with the values returned by `resolve`.
+/// The values are escaped as the contents of a Cedar string literal, so they cannot change the structure of the policy.
+pub fn render<F>(content: &str, mut resolve: F) -> Result<String, anyhow::Error>
+where
+    F: FnMut(TemplateVariable) -> Result<String, anyhow::Error>,
+{
+    let mut rendered = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let variable = &rest[start + 2..];
+        let end = variable
+            .find("}}")
+            .ok_or_else(|| anyhow!("Unterminated template variable"))?;
+        let value = resolve(TemplateVariable::parse(variable[..end].trim())?)?;
+        rendered.push_str(&value.escape_default().to_string());
+        rest = &variable[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Fills the template variables for the identity and the claims of its validated external token.
+pub fn instantiate(
+    content: &str,
+    identity: &ExternalIdentity,
+    claims: &HashMap<String, Value>,
+) -> Result<String, anyhow::Error> {
+    render(content, |variable| {
+        let value = match variable {
+            TemplateVariable::UserId => identity.user_id.clone(),
+            TemplateVariable::IdentityProvider => identity.identity_provider.clone(),
+            TemplateVariable::Claim(name) => match claims.get(name) {
+                Some(Value::String(value)) => value.clone(),
+                Some(Value::Number(value)) => value.to_string(),
+                Some(Value::Bool(value)) => value.to_string(),
+                Some(_) => bail!("Claim '{}' is not a single value", name),
+                None => bail!("Claim '{}' is missing", name),
+            },
+        };
+        // A wildcard would widen the `like` patterns the value is placed in
+        if value.contains('*') {
+            bail!("Value of {:?} contains a wildcard", variable);
+        }
+        Ok(value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[rstest]
+    #[case(
+        r#"permit(principal, action, resource) when { resource.path like "/home/{{user_id}}/*" };"#,
+        Ok(r#"permit(principal, action, resource) when { resource.path like "/home/user@example.com/*" };"#)
+    )]
+    #[case(
+        r#"permit(principal, action, resource) when { context.tenant == "{{ claims.tid }}" && context.idp == "{{identity_provider}}" };"#,
+        Ok(r#"permit(principal, action, resource) when { context.tenant == "tenant" && context.idp == "provider" };"#)
+    )]
+    #[case(r#"User::"{{claims.name}}""#, Ok(r#"User::"\"quoted\"""#))]
+    #[case(r#"User::"{{claims.level}}""#, Ok(r#"User::"3""#))]
+    #[case(r#"User::"{{claims.groups}}""#, Err(()))]
+    #[case(r#"User::"{{claims.pattern}}""#, Err(()))]
+    #[case(r#"User::"{{claims.missing}}""#, Err(()))]
+    #[case(r#"User::"{{unknown}}""#, Err(()))]
+    #[case(r#"User::"{{user_id"#, Err(()))]
+    fn test_instantiate(#[case] content: &str, #[case] expected: Result<&str, ()>) {
+        let identity =
+            ExternalIdentity::new("Provider".to_string(), "User@example.com".to_string());
+        let claims: HashMap<String, Value> = serde_json::from_value(json!({
+            "tid": "tenant",
+            "name": "\"quoted\"",
+            "level": 3,
+            "groups": ["admins"],
+            "pattern": "a*",
+        }))
+        .unwrap();
+        let result = instantiate(content, &identity, &claims);
+        assert_eq!(result.as_deref().map_err(|_| ()), expected);
+    }
+}
@@ -1,12 +1,16 @@
+use anyhow::anyhow;
+use jsonwebtoken::{DecodingKey, Validation};
+use std::collections::HashMap;
+
 /// Represents an external JWT Token used to authorize the `ExternalIdentity` and issue an `InternalToken`
 pub struct ExternalToken {
     pub token: String,
 }
 
 /// Allows `ExternalToken` to be converted to a String
-impl Into<String> for ExternalToken {
-    fn into(self) -> String {
-        self.token.clone()
+impl From<ExternalToken> for String {
+    fn from(value: ExternalToken) -> Self {
+        value.token
     }
 }
 
@@ -16,3 +20,60 @@ impl From<String> for ExternalToken {
         ExternalToken { token }
     }
 }
+
+impl ExternalToken {
+    /// Reads the `iss` claim without verifying the token.
+    /// The issuer is only used to choose the identity provider that validates the token.
+    pub fn unverified_issuer(&self) -> Result<String, anyhow::Error> {
+        let header = jsonwebtoken::decode_header(&self.token)?;
+        let mut validation = Validation::new(header.alg);
+        validation.insecure_disable_signature_validation();
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        validation.validate_aud = false;
+        let claims = jsonwebtoken::decode::<HashMap<String, serde_json::Value>>(
+            &self.token,
+            &DecodingKey::from_secret(&[]),
+            &validation,
+        )?
+        .claims;
+        claims
+            .get("iss")
+            .and_then(|issuer| issuer.as_str())
+            .map(|issuer| issuer.to_string())
+            .ok_or_else(|| anyhow!("Token has no issuer"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use rstest::rstest;
+
+    fn token(claims: serde_json::Value) -> ExternalToken {
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        ExternalToken::from(token)
+    }
+
+    #[rstest]
+    fn test_unverified_issuer() {
+        let token = token(serde_json::json!({"iss": "https://example.com/", "exp": 0}));
+        assert_eq!(token.unverified_issuer().unwrap(), "https://example.com/");
+    }
+
+    #[rstest]
+    fn test_missing_issuer() {
+        assert!(token(serde_json::json!({"sub": "user"}))
+            .unverified_issuer()
+            .is_err());
+        assert!(ExternalToken::from("not a token".to_string())
+            .unverified_issuer()
+            .is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Resources managed through the admin API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminResource {
+    Policy,
+    Role,
+    Identity,
+    Attachment,
+    Revocation,
+    Introspection,
+    Evaluation,
+}
+
+/// Actions that can be performed on the admin API resources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminAction {
+    Read,
+    Write,
+}
+
+/// Settings of the admin API authorization.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminSettings {
+    /// The name of the identity provider that authenticates the admin API callers.
+    pub identity_provider: String,
+
+    /// The permissions granted to the admin API callers, by user id.
+    pub users: HashMap<String, HashMap<AdminResource, Vec<AdminAction>>>,
+}
+
+impl AdminSettings {
+    /// Checks whether the user is allowed to perform the action on the resource.
+    pub fn is_allowed(&self, user_id: &str, resource: AdminResource, action: AdminAction) -> bool {
+        self.users
+            .iter()
+            .find(|(configured_user_id, _)| configured_user_id.to_lowercase() == user_id)
+            .and_then(|(_, permissions)| permissions.get(&resource))
+            .is_some_and(|actions| actions.contains(&action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("admin@example.com", AdminResource::Policy, AdminAction::Write, true)]
+    #[case("admin@example.com", AdminResource::Identity, AdminAction::Read, true)]
+    #[case(
+        "admin@example.com",
+        AdminResource::Identity,
+        AdminAction::Write,
+        false
+    )]
+    #[case(
+        "admin@example.com",
+        AdminResource::Attachment,
+        AdminAction::Read,
+        false
+    )]
+    #[case("user@example.com", AdminResource::Policy, AdminAction::Read, false)]
+    fn test_is_allowed(
+        #[case] user_id: &str,
+        #[case] resource: AdminResource,
+        #[case] action: AdminAction,
+        #[case] expected: bool,
+    ) {
+        let settings: AdminSettings = serde_yaml::from_str(
+            r#"
+identity_provider: azuread
+users:
+  Admin@Example.com:
+    policy: [read, write]
+    identity: [read]
+"#,
+        )
+        .unwrap();
+        assert_eq!(settings.is_allowed(user_id, resource, action), expected);
+    }
+}
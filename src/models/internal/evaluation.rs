@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Request of `POST /evaluate`: a Cedar authorization request for the principal authenticated as the identity
+#[derive(Debug, Deserialize)]
+pub struct EvaluationRequest {
+    /// The name of the identity provider of the identity
+    pub identity_provider: String,
+
+    /// The user id of the identity
+    pub user_id: String,
+
+    /// Claims of the external token, used for the claim attachments and the policy templates
+    #[serde(default)]
+    pub claims: HashMap<String, Value>,
+
+    /// The Cedar principal, e.g. `User::"user@example.com"`
+    pub principal: String,
+
+    /// The Cedar action, e.g. `Action::"read"`
+    pub action: String,
+
+    /// The Cedar resource, e.g. `Document::"report"`
+    pub resource: String,
+
+    /// The Cedar context as a JSON object
+    #[serde(default)]
+    pub context: Option<Value>,
+
+    /// The Cedar entities in the JSON format
+    #[serde(default)]
+    pub entities: Option<Value>,
+}
+
+/// The authorization decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvaluationDecision {
+    Allow,
+    Deny,
+}
+
+/// Response of `POST /evaluate`
+#[derive(Debug, Serialize)]
+pub struct EvaluationResponse {
+    pub decision: EvaluationDecision,
+
+    /// Ids of the evaluated policies, as carried by a token issued to the identity
+    pub policies: Vec<String>,
+
+    /// Ids of the policies that determined the decision
+    pub determining_policies: Vec<String>,
+
+    /// Errors encountered while evaluating the policies
+    pub errors: Vec<String>,
+}
@@ -1 +1,11 @@
+pub mod admin_settings;
+pub mod evaluation;
+pub mod openid_configuration;
+pub mod policy_settings;
+pub mod refresh_token;
+pub mod revocation;
+pub mod settings;
+pub mod signing_key;
+pub mod signing_key_settings;
+pub mod token_settings;
 pub mod v1;
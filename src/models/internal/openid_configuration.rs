@@ -0,0 +1,45 @@
+use crate::models::internal::signing_key::SigningAlgorithm;
+use jsonwebtoken::Algorithm;
+use serde::Serialize;
+
+/// OpenID Connect discovery document describing how to verify the tokens issued by `boxer-issuer`
+#[derive(Debug, Serialize)]
+pub struct OpenIdConfiguration {
+    /// The value of the `iss` claim in the issued tokens
+    pub issuer: String,
+
+    /// The URL of the JSON Web Key Set containing the token verification keys
+    pub jwks_uri: String,
+
+    /// The issued tokens are self-contained JWTs, like the ID tokens of OpenID Connect
+    pub response_types_supported: Vec<&'static str>,
+
+    /// The `sub` claim is the same for every consumer of the issued tokens
+    pub subject_types_supported: Vec<&'static str>,
+
+    /// The algorithms used to sign the issued tokens
+    pub id_token_signing_alg_values_supported: Vec<Algorithm>,
+}
+
+impl OpenIdConfiguration {
+    /// Creates a discovery document for the given issuer and algorithms of the signing keys
+    pub fn new(
+        issuer: String,
+        jwks_uri: String,
+        algorithms: impl IntoIterator<Item = SigningAlgorithm>,
+    ) -> Self {
+        let mut supported_algorithms: Vec<Algorithm> = Vec::new();
+        for algorithm in algorithms.into_iter().map(Algorithm::from) {
+            if !supported_algorithms.contains(&algorithm) {
+                supported_algorithms.push(algorithm);
+            }
+        }
+        OpenIdConfiguration {
+            issuer,
+            jwks_uri,
+            response_types_supported: vec!["id_token"],
+            subject_types_supported: vec!["public"],
+            id_token_signing_alg_values_supported: supported_algorithms,
+        }
+    }
+}
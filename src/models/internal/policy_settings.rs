@@ -0,0 +1,10 @@
+use serde::Deserialize;
+
+/// Validation of the policies stored through the admin API.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PolicySettings {
+    /// Path to a Cedar schema in the JSON format.
+    /// If set, policies are validated against the schema in addition to being parsed.
+    #[serde(default)]
+    pub schema_path: Option<String>,
+}
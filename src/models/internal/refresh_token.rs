@@ -0,0 +1,144 @@
+use crate::models::external::identity::ExternalIdentity;
+use crate::models::external::identity_provider::ExternalIdentityProvider;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A stored refresh token. Only the hash of the opaque token value is kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshToken {
+    /// SHA-256 hash of the token value
+    pub token_hash: String,
+
+    /// Identifies the chain of refresh tokens rotated from the same external token
+    pub family_id: String,
+
+    /// The name of the identity provider, as configured
+    pub identity_provider: String,
+
+    /// The id of the user the token was issued to
+    pub user_id: String,
+
+    /// The claims of the validated external token, so the claim attachments are evaluated on refresh
+    pub claims: HashMap<String, Value>,
+
+    /// The policies the token was issued for, or `None` if it was issued for all attached policies
+    pub policies: Option<BTreeSet<String>>,
+
+    /// The issuance time, in seconds since the Unix epoch
+    pub issued_at: u64,
+
+    /// The expiration time, in seconds since the Unix epoch
+    pub expires_at: u64,
+
+    /// Whether the token has already been exchanged for a new one
+    pub used: bool,
+}
+
+impl RefreshToken {
+    /// Generates a new refresh token in the family, or starts a new family if none is given.
+    /// Returns the opaque token value for the client together with the record to store.
+    pub fn generate(
+        provider: &ExternalIdentityProvider,
+        identity: &ExternalIdentity,
+        claims: HashMap<String, Value>,
+        family_id: Option<String>,
+        policies: Option<BTreeSet<String>>,
+        issued_at: SystemTime,
+        lifetime: Duration,
+    ) -> Result<(String, RefreshToken), anyhow::Error> {
+        let value = random_string()?;
+        let family_id = match family_id {
+            Some(family_id) => family_id,
+            None => random_string()?,
+        };
+        let issued_at = issued_at.duration_since(UNIX_EPOCH)?;
+        let record = RefreshToken {
+            token_hash: RefreshToken::hash(&value),
+            family_id,
+            identity_provider: provider.name(),
+            user_id: identity.user_id.clone(),
+            claims,
+            policies,
+            issued_at: issued_at.as_secs(),
+            expires_at: (issued_at + lifetime).as_secs(),
+            used: false,
+        };
+        Ok((value, record))
+    }
+
+    /// The identity provider that authenticated the user
+    pub fn provider(&self) -> ExternalIdentityProvider {
+        ExternalIdentityProvider::from(self.identity_provider.clone())
+    }
+
+    /// The identity the token was issued to
+    pub fn identity(&self) -> ExternalIdentity {
+        ExternalIdentity::new(self.identity_provider.clone(), self.user_id.clone())
+    }
+
+    /// Hashes the opaque token value to look up the stored token
+    pub fn hash(value: &str) -> String {
+        URL_SAFE_NO_PAD.encode(digest(&SHA256, value.as_bytes()))
+    }
+
+    /// Checks whether the token has expired at the given time
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+fn random_string() -> Result<String, anyhow::Error> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("Failed to generate a refresh token"))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_rotated_token_stays_in_family() {
+        let provider = ExternalIdentityProvider::from("Provider".to_string());
+        let identity = ExternalIdentity::new(provider.name(), "user".to_string());
+        let now = SystemTime::now();
+        let lifetime = Duration::from_secs(60);
+
+        let (first_value, first) = RefreshToken::generate(
+            &provider,
+            &identity,
+            HashMap::new(),
+            None,
+            None,
+            now,
+            lifetime,
+        )
+        .unwrap();
+        let (second_value, second) = RefreshToken::generate(
+            &provider,
+            &identity,
+            HashMap::new(),
+            Some(first.family_id.clone()),
+            None,
+            now,
+            lifetime,
+        )
+        .unwrap();
+
+        assert_ne!(first_value, second_value);
+        assert_eq!(first.family_id, second.family_id);
+        assert_eq!(first.provider(), provider);
+        assert_eq!(first.identity(), identity);
+        assert_eq!(first.token_hash, RefreshToken::hash(&first_value));
+        assert!(!first.is_expired(first.issued_at + 59));
+        assert!(first.is_expired(first.issued_at + 60));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::models::external::identity::ExternalIdentity;
+use crate::models::internal::v1::introspection::TokenDetails;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The tokens invalidated by a revocation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RevocationTarget {
+    /// A single token, identified by its `jti` claim
+    Token { token_id: String },
+
+    /// All tokens issued to an external identity
+    Identity {
+        identity_provider: String,
+        user_id: String,
+    },
+
+    /// All tokens carrying a policy
+    Policy { policy_id: String },
+}
+
+impl RevocationTarget {
+    /// Normalizes the target, so that identities are matched regardless of case.
+    pub fn normalize(self) -> Self {
+        match self {
+            RevocationTarget::Identity {
+                identity_provider,
+                user_id,
+            } => RevocationTarget::Identity {
+                identity_provider: identity_provider.to_lowercase(),
+                user_id: user_id.to_lowercase(),
+            },
+            target => target,
+        }
+    }
+}
+
+/// Invalidates the tokens of the target issued before or at `revoked_at`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Revocation {
+    #[serde(flatten)]
+    pub target: RevocationTarget,
+
+    /// The time of the revocation, in seconds since the Unix epoch
+    pub revoked_at: u64,
+
+    /// The time when all revoked tokens have expired and the revocation can be dropped,
+    /// in seconds since the Unix epoch
+    pub expires_at: u64,
+}
+
+impl Revocation {
+    /// Creates a revocation that lasts as long as the longest-living token issued before it.
+    pub fn new(
+        target: RevocationTarget,
+        revoked_at: SystemTime,
+        max_token_lifetime: Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let revoked_at = revoked_at.duration_since(UNIX_EPOCH)?;
+        Ok(Revocation {
+            target: target.normalize(),
+            revoked_at: revoked_at.as_secs(),
+            expires_at: (revoked_at + max_token_lifetime).as_secs(),
+        })
+    }
+
+    /// Checks whether the revoked tokens may still be valid at the given time.
+    pub fn is_active(&self, now: u64) -> bool {
+        self.expires_at > now
+    }
+
+    /// Checks whether the credentials issued to the identity at `issued_at` are invalidated by this revocation.
+    pub fn revokes_identity(&self, identity: &ExternalIdentity, issued_at: u64) -> bool {
+        match &self.target {
+            RevocationTarget::Identity {
+                identity_provider,
+                user_id,
+            } => {
+                issued_at <= self.revoked_at
+                    && &identity.identity_provider == identity_provider
+                    && &identity.user_id == user_id
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks whether the token is invalidated by this revocation.
+    pub fn revokes(&self, token: &TokenDetails) -> bool {
+        if token.iat > self.revoked_at {
+            return false;
+        }
+        match &self.target {
+            RevocationTarget::Token { token_id } => &token.jti == token_id,
+            RevocationTarget::Identity { .. } => self.revokes_identity(
+                &ExternalIdentity::new(token.identity_provider.clone(), token.user_id.clone()),
+                token.iat,
+            ),
+            RevocationTarget::Policy { policy_id } => token.policy_ids.contains(policy_id),
+        }
+    }
+}
+
+/// The published list of the active revocations.
+#[derive(Debug, Serialize)]
+pub struct RevocationList {
+    pub revocations: Vec<Revocation>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_revocation_expires_with_revoked_tokens() {
+        let revocation = Revocation::new(
+            RevocationTarget::Identity {
+                identity_provider: "AzureAD".to_string(),
+                user_id: "User@Example.com".to_string(),
+            },
+            UNIX_EPOCH + Duration::from_secs(1000),
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+
+        assert_eq!(
+            revocation.target,
+            RevocationTarget::Identity {
+                identity_provider: "azuread".to_string(),
+                user_id: "user@example.com".to_string(),
+            }
+        );
+        assert!(revocation.is_active(4599));
+        assert!(!revocation.is_active(4600));
+    }
+
+    fn details(iat: u64) -> TokenDetails {
+        TokenDetails {
+            token_type: "Bearer".to_string(),
+            sub: "azuread:user@example.com".to_string(),
+            jti: "token".to_string(),
+            iss: None,
+            aud: None,
+            iat,
+            nbf: Some(iat),
+            exp: iat + 3600,
+            user_id: "user@example.com".to_string(),
+            identity_provider: "azuread".to_string(),
+            api_version: "v1".to_string(),
+            policy: String::new(),
+            policy_ids: vec!["policy".to_string()],
+        }
+    }
+
+    #[rstest]
+    #[case(RevocationTarget::Token { token_id: "token".to_string() }, 1000, true)]
+    #[case(RevocationTarget::Token { token_id: "other".to_string() }, 1000, false)]
+    #[case(RevocationTarget::Identity { identity_provider: "AzureAD".to_string(), user_id: "User@Example.com".to_string() }, 1000, true)]
+    #[case(RevocationTarget::Identity { identity_provider: "azuread".to_string(), user_id: "user@example.com".to_string() }, 1001, false)]
+    #[case(RevocationTarget::Policy { policy_id: "policy".to_string() }, 999, true)]
+    #[case(RevocationTarget::Policy { policy_id: "other".to_string() }, 999, false)]
+    fn test_revokes(#[case] target: RevocationTarget, #[case] iat: u64, #[case] expected: bool) {
+        let revocation = Revocation::new(
+            target,
+            UNIX_EPOCH + Duration::from_secs(1000),
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        assert_eq!(revocation.revokes(&details(iat)), expected);
+    }
+
+    #[rstest]
+    #[case(r#"{"type":"token","token_id":"abc"}"#, RevocationTarget::Token { token_id: "abc".to_string() })]
+    #[case(r#"{"type":"policy","policy_id":"p1"}"#, RevocationTarget::Policy { policy_id: "p1".to_string() })]
+    fn test_parsing_revocation_target(#[case] json: &str, #[case] expected: RevocationTarget) {
+        let target: RevocationTarget = serde_json::from_str(json).unwrap();
+        assert_eq!(target, expected);
+    }
+}
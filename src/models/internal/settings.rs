@@ -0,0 +1,230 @@
+use crate::models::external::identity_provider::ExternalIdentityProvider;
+use crate::models::external::identity_provider_settings::OidcExternalIdentityProviderSettings;
+use crate::models::internal::admin_settings::AdminSettings;
+use crate::models::internal::policy_settings::PolicySettings;
+use crate::models::internal::signing_key_settings::SigningKeySettings;
+use crate::models::internal::token_settings::TokenSettings;
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Settings of the `boxer-issuer` service read from the configuration file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Settings {
+    /// External identity providers that are allowed to authenticate users, by provider name.
+    pub identity_providers: HashMap<String, OidcExternalIdentityProviderSettings>,
+
+    /// Storage for policies, identities and policy attachments.
+    #[serde(default)]
+    pub repository: RepositorySettings,
+
+    /// Authorization of the admin API. If not set, all admin API requests are rejected.
+    #[serde(default)]
+    pub admin: Option<AdminSettings>,
+
+    /// Settings of the issued tokens.
+    #[serde(default)]
+    pub token: TokenSettings,
+
+    /// Validation of the stored policies.
+    #[serde(default)]
+    pub policy: PolicySettings,
+
+    /// Keys for signing the issued tokens, referenced by the paths of their PEM files.
+    #[serde(default)]
+    pub signing_keys: Vec<SigningKeySettings>,
+}
+
+/// Storage backend for the repositories.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RepositorySettings {
+    /// Entities are kept in memory and lost on restart.
+    #[default]
+    InMemory,
+
+    /// Entities are stored in a SQLite database, e.g. `sqlite:///var/lib/boxer/boxer.db`.
+    Sqlite { url: String },
+
+    /// Entities are stored in a PostgreSQL database, e.g. `postgres://boxer@localhost:5432/boxer`.
+    Postgres {
+        url: String,
+        #[serde(default = "default_max_connections")]
+        max_connections: u32,
+    },
+}
+
+fn default_max_connections() -> u32 {
+    10
+}
+
+impl Settings {
+    /// Reads and validates the settings from a YAML document.
+    pub fn from_yaml(content: &str) -> Result<Self, anyhow::Error> {
+        let settings: Settings = serde_yaml::from_str(content)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Returns the identity provider settings keyed by the identity provider.
+    pub fn identity_providers(
+        &self,
+    ) -> HashMap<ExternalIdentityProvider, OidcExternalIdentityProviderSettings> {
+        self.identity_providers
+            .iter()
+            .map(|(name, settings)| {
+                (
+                    ExternalIdentityProvider::from(name.clone()),
+                    settings.clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        self.token
+            .validate()
+            .context("token: invalid token settings")?;
+        let mut names: Vec<&String> = self.identity_providers.keys().collect();
+        names.sort();
+        for name in names {
+            if name.trim().is_empty() {
+                bail!("identity_providers: provider name must not be empty");
+            }
+            let provider = &self.identity_providers[name];
+            provider.validate().with_context(|| {
+                format!("identity_providers.{}: invalid provider settings", name)
+            })?;
+            self.token
+                .with_override(provider.token.as_ref())
+                .validate()
+                .with_context(|| {
+                    format!("identity_providers.{}.token: invalid token settings", name)
+                })?;
+        }
+        let mut kids: Vec<&String> = self.signing_keys.iter().map(|key| &key.kid).collect();
+        kids.sort();
+        if let Some(kid) = kids.windows(2).find(|pair| pair[0] == pair[1]) {
+            bail!("signing_keys: duplicate kid '{}'", kid[0]);
+        }
+        if let Some(admin) = &self.admin {
+            if !self
+                .identity_providers
+                .contains_key(&admin.identity_provider)
+            {
+                bail!(
+                    "admin.identity_provider: unknown identity provider '{}'",
+                    admin.identity_provider
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::internal::signing_key::{SigningAlgorithm, SigningKeyState};
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_parsing_valid_settings() {
+        let content = r#"
+identity_providers:
+  azuread:
+    user_id_claim: upn
+    discovery_url: https://sts.windows.net/tenant/
+    issuers:
+      - https://sts.windows.net/tenant/
+    audiences:
+      - https://management.core.windows.net/
+"#;
+        let settings = Settings::from_yaml(content).unwrap();
+        let providers = settings.identity_providers();
+        let provider = &providers[&ExternalIdentityProvider::from("azuread".to_string())];
+        assert_eq!(provider.user_id_claim, "upn");
+        assert_eq!(provider.issuers, vec!["https://sts.windows.net/tenant/"]);
+    }
+
+    #[rstest]
+    fn test_admin_identity_provider_must_be_configured() {
+        let content = r#"
+identity_providers: {}
+admin:
+  identity_provider: azuread
+  users: {}
+"#;
+        let error = Settings::from_yaml(content).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "admin.identity_provider: unknown identity provider 'azuread'"
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "user_id_claim: ''",
+        "identity_providers.broken: invalid provider settings"
+    )]
+    #[case(
+        "discovery_url: sts.windows.net",
+        "identity_providers.broken: invalid provider settings"
+    )]
+    #[case("issuers: []", "identity_providers.broken: invalid provider settings")]
+    #[case("audiences: 42", "identity_providers.broken")]
+    fn test_parsing_invalid_settings(#[case] override_line: &str, #[case] expected: &str) {
+        let mut lines = vec![
+            "user_id_claim: upn",
+            "discovery_url: https://sts.windows.net/tenant/",
+            "issuers: [https://sts.windows.net/tenant/]",
+            "audiences: [https://management.core.windows.net/]",
+        ];
+        let key = override_line.split(':').next().unwrap();
+        lines.retain(|line| !line.starts_with(key));
+        lines.push(override_line);
+        let content = format!(
+            "identity_providers:\n  valid:\n    {}\n  broken:\n    {}\n",
+            "user_id_claim: upn\n    discovery_url: https://example.com/\n    issuers: [a]\n    audiences: [b]",
+            lines.join("\n    ")
+        );
+
+        let error = Settings::from_yaml(&content).unwrap_err();
+        assert!(
+            error.to_string().starts_with(expected),
+            "unexpected error: {}",
+            error
+        );
+    }
+
+    #[rstest]
+    fn test_parsing_signing_keys() {
+        let content = r#"
+identity_providers: {}
+signing_keys:
+  - { kid: "2024-10", algorithm: ES256, path: /keys/2024-10.pem, state: active }
+  - { kid: "2024-11", algorithm: EdDSA, path: /keys/2024-11.pem, state: pending }
+"#;
+        let settings = Settings::from_yaml(content).unwrap();
+        let kids: Vec<&str> = settings
+            .signing_keys
+            .iter()
+            .map(|key| key.kid.as_str())
+            .collect();
+        assert_eq!(kids, vec!["2024-10", "2024-11"]);
+        assert_eq!(settings.signing_keys[1].algorithm, SigningAlgorithm::EdDsa);
+        assert_eq!(settings.signing_keys[1].state, SigningKeyState::Pending);
+    }
+
+    #[rstest]
+    fn test_signing_key_ids_must_be_unique() {
+        let content = r#"
+identity_providers: {}
+signing_keys:
+  - { kid: "2024-10", algorithm: ES256, path: /keys/a.pem, state: active }
+  - { kid: "2024-10", algorithm: ES256, path: /keys/b.pem, state: retired }
+"#;
+        let error = Settings::from_yaml(content).unwrap_err();
+        assert_eq!(error.to_string(), "signing_keys: duplicate kid '2024-10'");
+    }
+}
@@ -0,0 +1,18 @@
+use crate::models::internal::signing_key::{SigningAlgorithm, SigningKeyState};
+use serde::Deserialize;
+
+/// Settings of a single key in the signing key ring
+#[derive(Debug, Clone, Deserialize)]
+pub struct SigningKeySettings {
+    /// The key id written to the `kid` header of the issued tokens.
+    pub kid: String,
+
+    /// The algorithm used to sign the tokens with this key.
+    pub algorithm: SigningAlgorithm,
+
+    /// Path to a PEM-encoded private key.
+    pub path: String,
+
+    /// The requested state of the key. Only one key can be active at a time.
+    pub state: SigningKeyState,
+}
@@ -0,0 +1,206 @@
+use anyhow::bail;
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+
+/// Settings of the internal tokens issued by `boxer-issuer`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TokenSettings {
+    /// The value of the `iss` claim, the `public_url` if not set
+    pub issuer: Option<String>,
+
+    /// The value of the `aud` claim
+    pub audience: String,
+
+    /// The lifetime of the issued tokens, in seconds
+    pub lifetime_seconds: u64,
+
+    /// The upper bound for the lifetime overrides of the identity providers, in seconds.
+    /// Signing keys are published for this long after they are retired.
+    pub max_lifetime_seconds: u64,
+
+    /// The lifetime of the refresh tokens since their last use, in seconds.
+    /// Refresh tokens are not issued if not set.
+    pub refresh_token_lifetime_seconds: Option<u64>,
+
+    /// The base URL `boxer-issuer` is reachable at by the token consumers, used for the URLs in the discovery document
+    pub public_url: String,
+}
+
+impl Default for TokenSettings {
+    fn default() -> Self {
+        TokenSettings {
+            issuer: None,
+            audience: "boxer.sneaksanddata.com".to_string(),
+            lifetime_seconds: 3600,
+            max_lifetime_seconds: 3600,
+            refresh_token_lifetime_seconds: None,
+            public_url: "https://boxer.sneaksanddata.com".to_string(),
+        }
+    }
+}
+
+/// Overrides of the token settings for the tokens issued to the users of an identity provider.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TokenSettingsOverride {
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub lifetime_seconds: Option<u64>,
+}
+
+impl TokenSettings {
+    /// Applies the identity provider overrides to the settings.
+    pub fn with_override(&self, value: Option<&TokenSettingsOverride>) -> TokenSettings {
+        let Some(value) = value else {
+            return self.clone();
+        };
+        TokenSettings {
+            issuer: value.issuer.clone().or(self.issuer.clone()),
+            audience: value.audience.clone().unwrap_or(self.audience.clone()),
+            lifetime_seconds: value.lifetime_seconds.unwrap_or(self.lifetime_seconds),
+            max_lifetime_seconds: self.max_lifetime_seconds,
+            refresh_token_lifetime_seconds: self.refresh_token_lifetime_seconds,
+            public_url: self.public_url.clone(),
+        }
+    }
+
+    /// The longest lifetime of a token issued with these settings or their overrides.
+    pub fn max_lifetime(&self) -> Duration {
+        Duration::from_secs(self.max_lifetime_seconds)
+    }
+
+    /// The lifetime of the refresh tokens, if they are enabled.
+    pub fn refresh_token_lifetime(&self) -> Option<Duration> {
+        self.refresh_token_lifetime_seconds.map(Duration::from_secs)
+    }
+
+    /// How long a revocation has to be kept: until all tokens and refresh tokens issued before it have expired.
+    pub fn revocation_lifetime(&self) -> Duration {
+        self.max_lifetime()
+            .max(self.refresh_token_lifetime().unwrap_or_default())
+    }
+
+    /// The value of the `iss` claim of the issued tokens.
+    pub fn issuer(&self) -> String {
+        self.issuer.clone().unwrap_or(self.public_url.clone())
+    }
+
+    /// The URL of the JSON Web Key Set published by `boxer-issuer`.
+    pub fn jwks_uri(&self) -> String {
+        format!(
+            "{}/.well-known/jwks.json",
+            self.public_url.trim_end_matches('/')
+        )
+    }
+
+    /// Returns the expiration time of a token issued now.
+    /// The token never outlives `not_after`, usually the expiration time of the external token.
+    pub fn expiration(&self, now: SystemTime, not_after: Option<SystemTime>) -> SystemTime {
+        let expiration = now + Duration::from_secs(self.lifetime_seconds);
+        match not_after {
+            Some(not_after) if not_after < expiration => not_after,
+            _ => expiration,
+        }
+    }
+
+    /// Checks that the lifetime does not exceed the maximum lifetime.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.lifetime_seconds == 0 {
+            bail!("lifetime_seconds must be positive");
+        }
+        if self.refresh_token_lifetime_seconds == Some(0) {
+            bail!("refresh_token_lifetime_seconds must be positive");
+        }
+        if !is_url(&self.public_url) {
+            bail!("public_url must be an http or https URL");
+        }
+        if !is_url(&self.issuer()) {
+            bail!("issuer must be an http or https URL");
+        }
+        if self.lifetime_seconds > self.max_lifetime_seconds {
+            bail!(
+                "lifetime_seconds ({}) must not exceed max_lifetime_seconds ({})",
+                self.lifetime_seconds,
+                self.max_lifetime_seconds
+            );
+        }
+        Ok(())
+    }
+}
+
+fn is_url(value: &str) -> bool {
+    value.starts_with("https://") || value.starts_with("http://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(None, 3600)]
+    #[case(Some(7200), 3600)]
+    #[case(Some(600), 600)]
+    fn test_expiration_is_capped_by_external_token(
+        #[case] not_after_seconds: Option<u64>,
+        #[case] expected_seconds: u64,
+    ) {
+        let now = SystemTime::now();
+        let settings = TokenSettings::default();
+        let not_after = not_after_seconds.map(|s| now + Duration::from_secs(s));
+        assert_eq!(
+            settings.expiration(now, not_after),
+            now + Duration::from_secs(expected_seconds)
+        );
+    }
+
+    #[rstest]
+    fn test_override() {
+        let settings = TokenSettings::default().with_override(Some(&TokenSettingsOverride {
+            issuer: None,
+            audience: Some("batch".to_string()),
+            lifetime_seconds: Some(600),
+        }));
+        assert_eq!(settings.issuer(), "https://boxer.sneaksanddata.com");
+        assert_eq!(settings.audience, "batch");
+        assert_eq!(settings.lifetime_seconds, 600);
+    }
+
+    #[rstest]
+    #[case("https://boxer.example.com")]
+    #[case("https://boxer.example.com/")]
+    fn test_jwks_uri(#[case] public_url: &str) {
+        let settings = TokenSettings {
+            public_url: public_url.to_string(),
+            ..TokenSettings::default()
+        };
+        assert_eq!(
+            settings.jwks_uri(),
+            "https://boxer.example.com/.well-known/jwks.json"
+        );
+    }
+
+    #[rstest]
+    #[case(None, "https://boxer.example.com")]
+    #[case(Some("https://issuer.example.com"), "https://issuer.example.com")]
+    fn test_issuer_defaults_to_public_url(#[case] issuer: Option<&str>, #[case] expected: &str) {
+        let settings = TokenSettings {
+            issuer: issuer.map(|issuer| issuer.to_string()),
+            public_url: "https://boxer.example.com".to_string(),
+            ..TokenSettings::default()
+        };
+        assert_eq!(settings.issuer(), expected);
+    }
+
+    #[rstest]
+    #[case(Some("https://issuer.example.com"), true)]
+    #[case(Some("boxer.sneaksanddata.com"), false)]
+    #[case(None, true)]
+    fn test_validate_issuer(#[case] issuer: Option<&str>, #[case] valid: bool) {
+        let settings = TokenSettings {
+            issuer: issuer.map(|issuer| issuer.to_string()),
+            ..TokenSettings::default()
+        };
+        assert_eq!(settings.validate().is_ok(), valid);
+    }
+}
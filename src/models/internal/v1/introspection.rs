@@ -0,0 +1,99 @@
+use crate::models::internal::v1::token::{
+    decode_policy, API_VERSION_KEY, IDENTITY_PROVIDER_KEY, POLICY_IDS_KEY, POLICY_KEY, USER_ID_KEY,
+};
+use anyhow::anyhow;
+use jwt::Claims;
+use serde::Serialize;
+
+/// Response of the token introspection endpoint, as defined by RFC 7662
+#[derive(Debug, Serialize)]
+pub struct TokenIntrospection {
+    /// Whether the token is valid, not expired and not revoked
+    pub active: bool,
+
+    /// Metadata of the token, only present for active tokens
+    #[serde(flatten)]
+    pub details: Option<TokenDetails>,
+}
+
+impl TokenIntrospection {
+    /// The response for tokens that are invalid, expired or revoked
+    pub fn inactive() -> Self {
+        TokenIntrospection {
+            active: false,
+            details: None,
+        }
+    }
+
+    /// The response for valid tokens
+    pub fn active(details: TokenDetails) -> Self {
+        TokenIntrospection {
+            active: true,
+            details: Some(details),
+        }
+    }
+}
+
+/// Metadata of a boxer token decoded from its claims
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TokenDetails {
+    pub token_type: String,
+    pub sub: String,
+    pub jti: String,
+    pub iss: Option<String>,
+    pub aud: Option<String>,
+    pub iat: u64,
+    pub nbf: Option<u64>,
+    pub exp: u64,
+    pub user_id: String,
+    pub identity_provider: String,
+    pub api_version: String,
+    pub policy: String,
+    pub policy_ids: Vec<String>,
+}
+
+impl TryFrom<Claims> for TokenDetails {
+    type Error = anyhow::Error;
+
+    fn try_from(claims: Claims) -> Result<Self, Self::Error> {
+        let private = |key: &str| {
+            claims
+                .private
+                .get(key)
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string())
+                .ok_or_else(|| anyhow!("Missing claim {}", key))
+        };
+        let policy_ids = match claims.private.get(POLICY_IDS_KEY) {
+            Some(value) => serde_json::from_value(value.clone())?,
+            None => Vec::new(),
+        };
+        Ok(TokenDetails {
+            token_type: "Bearer".to_string(),
+            user_id: private(USER_ID_KEY)?,
+            identity_provider: private(IDENTITY_PROVIDER_KEY)?,
+            api_version: private(API_VERSION_KEY)?,
+            policy: decode_policy(&private(POLICY_KEY)?)?,
+            policy_ids,
+            sub: claims
+                .registered
+                .subject
+                .ok_or_else(|| anyhow!("Missing claim sub"))?,
+            jti: claims
+                .registered
+                .json_web_token_id
+                .ok_or_else(|| anyhow!("Missing claim jti"))?,
+            iss: claims.registered.issuer,
+            aud: claims.registered.audience,
+            iat: claims
+                .registered
+                .issued_at
+                .ok_or_else(|| anyhow!("Missing claim iat"))?,
+            nbf: claims.registered.not_before,
+            exp: claims
+                .registered
+                .expiration
+                .ok_or_else(|| anyhow!("Missing claim exp"))?,
+        })
+    }
+}
@@ -1 +1,4 @@
+pub mod introspection;
 pub mod token;
+pub mod token_exchange;
+pub mod token_explanation;
@@ -1,54 +1,106 @@
 use crate::models::external::identity::Policy;
 use crate::models::external::identity_provider::ExternalIdentityProvider;
 use base64::engine::general_purpose::STANDARD;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
+use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use jwt::Claims;
-use std::io::Write;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// This claim should be always present in the boxer token
+pub const API_VERSION_KEY: &str = "boxer.sneaksanddata.com/api-version";
+
+// Constants related to a particular API version
+pub const POLICY_KEY: &str = "boxer.sneaksanddata.com/policy";
+pub const POLICY_IDS_KEY: &str = "boxer.sneaksanddata.com/policy-ids";
+pub const USER_ID_KEY: &str = "boxer.sneaksanddata.com/user-id";
+pub const IDENTITY_PROVIDER_KEY: &str = "boxer.sneaksanddata.com/identity-provider";
 
 /// Represents an internal JWT Token issued by `boxer-issuer`
 pub struct InternalToken {
     pub policy: Policy,
+    /// Ids of the policies merged into `policy`
+    pub policy_ids: Vec<String>,
     pub metadata: TokenMetadata,
     version: String,
 }
 
 pub struct TokenMetadata {
+    /// Unique identifier of the token, written to the `jti` claim
+    pub token_id: String,
     pub user_id: String,
     pub identity_provider: ExternalIdentityProvider,
+    pub issuer: String,
+    pub audience: String,
+    pub issued_at: SystemTime,
+    pub expires_at: SystemTime,
+}
+
+impl TokenMetadata {
+    /// The subject of the token, unique across the identity providers
+    pub fn subject(&self) -> String {
+        format!("{}:{}", self.identity_provider.name(), self.user_id)
+    }
 }
 
 impl InternalToken {
-    pub fn new(policy: Policy, user_id: String, external_identity_provider: String) -> Self {
-        InternalToken {
+    /// Creates a token that carries the merged policies, in the order of their ids
+    pub fn new(
+        policies: BTreeMap<String, Policy>,
+        user_id: String,
+        external_identity_provider: String,
+        issuer: String,
+        audience: String,
+        issued_at: SystemTime,
+        expires_at: SystemTime,
+    ) -> Result<Self, anyhow::Error> {
+        let mut policy = Policy::empty();
+        for p in policies.values() {
+            policy = policy.merge(p.clone());
+        }
+        Ok(InternalToken {
             policy,
+            policy_ids: policies.into_keys().collect(),
             metadata: TokenMetadata {
+                token_id: generate_token_id()?,
                 user_id,
                 identity_provider: ExternalIdentityProvider::from(external_identity_provider),
+                issuer,
+                audience,
+                issued_at,
+                expires_at,
             },
             version: "v1".to_string(),
-        }
+        })
     }
 }
 
+/// Restores the policy content from the compressed `boxer.sneaksanddata.com/policy` claim
+pub fn decode_policy(encoded: &str) -> Result<String, anyhow::Error> {
+    let compressed = STANDARD.decode(encoded)?;
+    let mut policy = String::new();
+    ZlibDecoder::new(compressed.as_slice()).read_to_string(&mut policy)?;
+    Ok(policy)
+}
+
+fn generate_token_id() -> Result<String, anyhow::Error> {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("Failed to generate a token id"))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
 impl TryInto<Claims> for InternalToken {
     type Error = anyhow::Error;
 
     fn try_into(self) -> Result<Claims, Self::Error> {
-        // This claim should be always present in the boxer token
-        const API_VERSION_KEY: &str = "boxer.sneaksanddata.com/api-version";
-
-        // Constants related to a particular API version
-        const POLICY_KEY: &str = "boxer.sneaksanddata.com/policy";
-        const USER_ID_KEY: &str = "boxer.sneaksanddata.com/user-id";
-        const IDENTITY_PROVIDER_KEY: &str = "boxer.sneaksanddata.com/identity-provider";
-
-        // The constants below to be moved in the service configuration file in the future.
-        const BOXER_ISSUER: &str = "boxer.sneaksanddata.com";
-        const BOXER_AUDIENCE: &str = "boxer.sneaksanddata.com";
-
+        let subject = self.metadata.subject();
         let compressed_policy = {
             let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
             encoder.write_all(self.policy.content.as_bytes())?;
@@ -63,6 +115,9 @@ impl TryInto<Claims> for InternalToken {
             POLICY_KEY.to_string(),
             STANDARD.encode(&compressed_policy).into(),
         );
+        claims
+            .private
+            .insert(POLICY_IDS_KEY.to_string(), self.policy_ids.into());
         claims
             .private
             .insert(USER_ID_KEY.to_string(), self.metadata.user_id.into());
@@ -71,10 +126,81 @@ impl TryInto<Claims> for InternalToken {
             self.metadata.identity_provider.name().into(),
         );
 
-        claims.registered.issuer = Some(BOXER_ISSUER.to_string());
-        claims.registered.audience = Some(BOXER_AUDIENCE.to_string());
-        let one_hour = SystemTime::now() + Duration::from_secs(3600);
-        claims.registered.expiration = Some(one_hour.duration_since(UNIX_EPOCH)?.as_secs());
+        let issued_at = self
+            .metadata
+            .issued_at
+            .duration_since(UNIX_EPOCH)?
+            .as_secs();
+        claims.registered.subject = Some(subject);
+        claims.registered.json_web_token_id = Some(self.metadata.token_id);
+        claims.registered.issued_at = Some(issued_at);
+        claims.registered.not_before = Some(issued_at);
+        claims.registered.issuer = Some(self.metadata.issuer);
+        claims.registered.audience = Some(self.metadata.audience);
+        claims.registered.expiration = Some(
+            self.metadata
+                .expires_at
+                .duration_since(UNIX_EPOCH)?
+                .as_secs(),
+        );
         Ok(claims)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::time::Duration;
+
+    fn token(issued_at: SystemTime) -> InternalToken {
+        InternalToken::new(
+            BTreeMap::from([
+                ("b".to_string(), Policy::new("second".to_string())),
+                ("a".to_string(), Policy::new("first".to_string())),
+            ]),
+            "user@example.com".to_string(),
+            "azuread".to_string(),
+            "issuer".to_string(),
+            "audience".to_string(),
+            issued_at,
+            issued_at + Duration::from_secs(3600),
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    fn test_registered_claims() {
+        let issued_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let token = token(issued_at);
+        let token_id = token.metadata.token_id.clone();
+
+        let claims: Claims = token.try_into().unwrap();
+
+        assert_eq!(
+            claims.registered.subject,
+            Some("azuread:user@example.com".to_string())
+        );
+        assert_eq!(claims.registered.json_web_token_id, Some(token_id));
+        assert_eq!(claims.registered.issued_at, Some(1_700_000_000));
+        assert_eq!(claims.registered.not_before, Some(1_700_000_000));
+        assert_eq!(claims.registered.expiration, Some(1_700_003_600));
+        assert_eq!(
+            claims.private["boxer.sneaksanddata.com/policy-ids"],
+            serde_json::json!(["a", "b"])
+        );
+    }
+
+    #[rstest]
+    fn test_policy_claim_round_trip() {
+        let claims: Claims = token(SystemTime::now()).try_into().unwrap();
+        let encoded = claims.private[POLICY_KEY].as_str().unwrap();
+        assert_eq!(decode_policy(encoded).unwrap(), "\nfirst\nsecond");
+    }
+
+    #[rstest]
+    fn test_token_ids_are_unique() {
+        let now = SystemTime::now();
+        assert_ne!(token(now).metadata.token_id, token(now).metadata.token_id);
+    }
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// The grant type of the token exchange requests, as defined by RFC 8693
+pub const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+
+/// The grant type of the refresh requests, as defined by RFC 6749
+pub const REFRESH_TOKEN_GRANT_TYPE: &str = "refresh_token";
+
+/// The type of the tokens issued by `boxer-issuer`
+pub const JWT_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:jwt";
+
+/// External token types accepted as the subject token
+pub const SUBJECT_TOKEN_TYPES: [&str; 3] = [
+    JWT_TOKEN_TYPE,
+    "urn:ietf:params:oauth:token-type:access_token",
+    "urn:ietf:params:oauth:token-type:id_token",
+];
+
+/// Token types that can be requested from `boxer-issuer`
+pub const REQUESTED_TOKEN_TYPES: [&str; 2] = [
+    JWT_TOKEN_TYPE,
+    "urn:ietf:params:oauth:token-type:access_token",
+];
+
+/// Form parameters of the token endpoint requests.
+/// The subject token parameters are required by the token exchange grant, the refresh token by the refresh grant.
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub subject_token: Option<String>,
+    pub subject_token_type: Option<String>,
+    pub refresh_token: Option<String>,
+    pub requested_token_type: Option<String>,
+    pub audience: Option<String>,
+    pub scope: Option<String>,
+}
+
+/// Query parameters of `GET /token/{identity_provider}`
+#[derive(Debug, Deserialize)]
+pub struct TokenQuery {
+    pub scope: Option<String>,
+}
+
+/// Reads the policy ids requested with a space-separated scope.
+/// Returns `None` if no policies are requested, so the token carries all attached policies.
+pub fn requested_policies(scope: Option<&str>) -> Option<BTreeSet<String>> {
+    let policies: BTreeSet<String> = scope?.split_whitespace().map(|p| p.to_string()).collect();
+    match policies.is_empty() {
+        true => None,
+        false => Some(policies),
+    }
+}
+
+/// Successful response of the token endpoint
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub issued_token_type: String,
+    pub token_type: String,
+    pub expires_in: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+impl TokenResponse {
+    /// Creates a response for a bearer JWT that expires in `expires_in` seconds
+    pub fn new(access_token: String, expires_in: u64, refresh_token: Option<String>) -> Self {
+        TokenResponse {
+            access_token,
+            issued_token_type: JWT_TOKEN_TYPE.to_string(),
+            token_type: "Bearer".to_string(),
+            expires_in,
+            refresh_token,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(None, None)]
+    #[case(Some(""), None)]
+    #[case(Some("  "), None)]
+    #[case(Some("read write read"), Some(vec!["read", "write"]))]
+    fn test_requested_policies(#[case] scope: Option<&str>, #[case] expected: Option<Vec<&str>>) {
+        let expected =
+            expected.map(|policies| policies.into_iter().map(|p| p.to_string()).collect());
+        assert_eq!(requested_policies(scope), expected);
+    }
+}
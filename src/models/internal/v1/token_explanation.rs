@@ -0,0 +1,141 @@
+use crate::models::external::claim_attachment::ClaimAttachment;
+use crate::models::external::identity::{ExternalIdentity, PolicyAttachment, Role};
+use jwt::Claims;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The attachments that contribute policies to a token
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MatchedAttachments {
+    /// The attachment of the identity, if any
+    pub identity: Option<PolicyAttachment>,
+
+    /// The claim attachments whose predicates are satisfied by the external token
+    pub claims: Vec<ClaimAttachment>,
+
+    /// The attached roles, by id
+    pub roles: BTreeMap<String, Role>,
+
+    /// Ids of the attached roles that do not exist and are skipped
+    pub missing_roles: Vec<String>,
+}
+
+impl MatchedAttachments {
+    /// Ids of the policies attached directly, through the claim predicates and through the roles
+    pub fn policies(&self) -> BTreeSet<String> {
+        let identity = self.identity.iter().flat_map(|a| a.policies.iter());
+        let claims = self
+            .claims
+            .iter()
+            .flat_map(|a| a.attachment.policies.iter());
+        let roles = self.roles.values().flat_map(|r| r.policies.iter());
+        identity.chain(claims).chain(roles).cloned().collect()
+    }
+
+    /// Revisions the attached policies are pinned to, by policy id.
+    /// A policy pinned by several attachments uses the latest of the pinned revisions.
+    pub fn revisions(&self) -> BTreeMap<String, u64> {
+        let identity = self.identity.iter().flat_map(|a| a.revisions.iter());
+        let claims = self
+            .claims
+            .iter()
+            .flat_map(|a| a.attachment.revisions.iter());
+        let mut revisions = BTreeMap::new();
+        for (policy, revision) in identity.chain(claims) {
+            let pinned = revisions.entry(policy.clone()).or_insert(*revision);
+            *pinned = (*pinned).max(*revision);
+        }
+        revisions
+    }
+
+    /// Ids of the roles attached directly and through the claim predicates
+    pub fn role_ids(&self) -> BTreeSet<String> {
+        let identity = self.identity.iter().flat_map(|a| a.roles.iter());
+        let claims = self.claims.iter().flat_map(|a| a.attachment.roles.iter());
+        identity.chain(claims).cloned().collect()
+    }
+}
+
+/// Sizes of the merged policy, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PolicySize {
+    /// The merged policy content
+    pub uncompressed: usize,
+
+    /// The zlib-compressed policy
+    pub compressed: usize,
+
+    /// The base64-encoded compressed policy, as written to the `boxer.sneaksanddata.com/policy` claim
+    pub encoded: usize,
+}
+
+/// Response of `GET /token/{identity_provider}/explain`: how the token for the external token would be issued
+#[derive(Debug, Serialize)]
+pub struct TokenExplanation {
+    /// The identity resolved from the external token
+    pub identity: ExternalIdentity,
+
+    /// The claim of the external token used as the user id
+    pub user_id_claim: String,
+
+    /// The attachments that contribute policies
+    pub attachments: MatchedAttachments,
+
+    /// Ids of the policies carried by the token, in the order they are merged
+    pub policies: Vec<String>,
+
+    /// Ids of the attached policies that do not exist and are skipped
+    pub missing_policies: Vec<String>,
+
+    /// Sizes of the merged policy
+    pub policy_size: PolicySize,
+
+    /// The claims that would be signed
+    pub claims: Claims,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::external::claim_attachment::{ClaimOperator, ClaimPredicate};
+    use rstest::rstest;
+    use std::collections::{HashMap, HashSet};
+
+    #[rstest]
+    fn test_matched_attachments() {
+        let mut identity = PolicyAttachment::pinned("direct".to_string(), Some(2));
+        identity.merge(PolicyAttachment::role("readers".to_string()));
+        let attachments = MatchedAttachments {
+            identity: Some(identity),
+            claims: vec![ClaimAttachment {
+                predicate: ClaimPredicate {
+                    claim: "groups".to_string(),
+                    operator: ClaimOperator::Contains,
+                    value: "admins".to_string(),
+                },
+                attachment: PolicyAttachment {
+                    policies: HashSet::from(["admin".to_string(), "direct".to_string()]),
+                    roles: HashSet::new(),
+                    revisions: HashMap::from([("direct".to_string(), 1)]),
+                },
+            }],
+            roles: BTreeMap::from([(
+                "readers".to_string(),
+                Role {
+                    policies: HashSet::from(["reader".to_string()]),
+                },
+            )]),
+            missing_roles: vec![],
+        };
+        let policies: Vec<String> = attachments.policies().into_iter().collect();
+        assert_eq!(policies, vec!["admin", "direct", "reader"]);
+        assert_eq!(
+            attachments.role_ids(),
+            BTreeSet::from(["readers".to_string()])
+        );
+        assert_eq!(
+            attachments.revisions(),
+            BTreeMap::from([("direct".to_string(), 2)])
+        );
+    }
+}
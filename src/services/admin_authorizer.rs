@@ -0,0 +1,107 @@
+use crate::models::external::identity::ExternalIdentity;
+use crate::models::external::identity_provider::ExternalIdentityProvider;
+use crate::models::external::token::ExternalToken;
+use crate::models::internal::admin_settings::{AdminAction, AdminResource, AdminSettings};
+use crate::services::identity_validator_provider::{
+    ExternalIdentityValidationService, ExternalIdentityValidatorProvider,
+};
+use async_trait::async_trait;
+use log::{info, warn};
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+/// Errors returned when an admin API request is not authorized
+#[derive(Debug)]
+pub enum AuthorizationError {
+    /// The caller could not be authenticated
+    Unauthenticated(String),
+
+    /// The caller is authenticated, but is not allowed to perform the action
+    Forbidden(String),
+}
+
+impl Display for AuthorizationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthorizationError::Unauthenticated(reason) => write!(f, "{}", reason),
+            AuthorizationError::Forbidden(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AuthorizationError {}
+
+/// Authorizes the callers of the admin API.
+#[async_trait]
+pub trait AdminAuthorizer {
+    /// Authenticates the caller with the token and checks that the caller is allowed
+    /// to perform the action on the resource. Returns the identity of the caller.
+    async fn authorize(
+        &self,
+        token: ExternalToken,
+        resource: AdminResource,
+        action: AdminAction,
+    ) -> Result<ExternalIdentity, AuthorizationError>;
+}
+
+pub struct AdminAuthorizationService {
+    validators: Arc<ExternalIdentityValidationService>,
+    settings: Option<AdminSettings>,
+}
+
+#[async_trait]
+impl AdminAuthorizer for AdminAuthorizationService {
+    async fn authorize(
+        &self,
+        token: ExternalToken,
+        resource: AdminResource,
+        action: AdminAction,
+    ) -> Result<ExternalIdentity, AuthorizationError> {
+        let settings = self
+            .settings
+            .as_ref()
+            .ok_or_else(|| AuthorizationError::Forbidden("Admin API is not enabled".to_string()))?;
+        let provider = ExternalIdentityProvider::from(settings.identity_provider.clone());
+        let validator = self.validators.get(provider).await.map_err(|e| {
+            warn!("Admin identity provider is not available: {:?}", e);
+            AuthorizationError::Unauthenticated(
+                "Admin identity provider is not available".to_string(),
+            )
+        })?;
+        let identity = validator
+            .validate(token)
+            .await
+            .map_err(|e| {
+                warn!("Failed to authenticate admin API caller: {:?}", e);
+                AuthorizationError::Unauthenticated("Invalid token".to_string())
+            })?
+            .identity;
+        if !settings.is_allowed(&identity.user_id, resource, action) {
+            warn!(
+                "User {} is not allowed to {:?} {:?}",
+                identity.user_id, action, resource
+            );
+            return Err(AuthorizationError::Forbidden(format!(
+                "Not allowed to {:?} {:?}",
+                action, resource
+            )));
+        }
+        info!(
+            "User {} is allowed to {:?} {:?}",
+            identity.user_id, action, resource
+        );
+        Ok(identity)
+    }
+}
+
+impl AdminAuthorizationService {
+    pub fn new(
+        validators: Arc<ExternalIdentityValidationService>,
+        settings: Option<AdminSettings>,
+    ) -> Self {
+        AdminAuthorizationService {
+            validators,
+            settings,
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use crate::models::external::claim_attachment::{ClaimAttachment, ClaimAttachmentKey};
+use crate::models::external::identity::PolicyAttachment;
+use crate::services::base::upsert_repository::{RepositoryError, UpsertRepository};
+use async_trait::async_trait;
+
+#[async_trait]
+/// Represents a repository for the policies attached to claim predicates.
+/// Upserts merge the policies with the existing ones, like the attachments to individual identities.
+pub trait ClaimAttachmentStore:
+    UpsertRepository<PolicyAttachment, ClaimAttachmentKey, Error = RepositoryError>
+{
+    /// Lists the claim attachments of the identity provider, by its case-insensitive name
+    async fn list_by_provider(
+        &self,
+        identity_provider: &str,
+    ) -> Result<Vec<ClaimAttachment>, RepositoryError>;
+}
+
+pub type ClaimAttachmentRepository = dyn ClaimAttachmentStore + Send + Sync;
@@ -0,0 +1,18 @@
+use crate::models::internal::revocation::Revocation;
+use crate::services::base::upsert_repository::RepositoryError;
+use async_trait::async_trait;
+
+#[async_trait]
+/// Represents a repository for entities that are only kept until they expire
+pub trait ExpiringRepository<Entity> {
+    type Error;
+
+    /// Inserts the entity, replacing the existing entity with the same key, and drops the expired entities
+    async fn insert(&self, entity: Entity) -> Result<(), Self::Error>;
+
+    /// Lists the entities that have not expired at the given time, in seconds since the Unix epoch
+    async fn list_active(&self, now: u64) -> Result<Vec<Entity>, Self::Error>;
+}
+
+pub type RevocationRepository =
+    dyn ExpiringRepository<Revocation, Error = RepositoryError> + Send + Sync;
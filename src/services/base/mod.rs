@@ -1 +1,5 @@
+pub mod claim_attachment_store;
+pub mod expiring_repository;
+pub mod policy_revision_store;
+pub mod refresh_token_store;
 pub mod upsert_repository;
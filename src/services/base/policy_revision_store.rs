@@ -0,0 +1,31 @@
+use crate::models::external::identity::Policy;
+use crate::models::external::policy_revision::{PolicyChange, PolicyRevision};
+use crate::services::base::upsert_repository::{Precondition, RepositoryError, UpsertRepository};
+use async_trait::async_trait;
+
+#[async_trait]
+/// Represents a repository for policies that keeps every change as an immutable revision.
+/// Upserts store a new revision without author and reason, and the revisions are kept when a policy is deleted.
+pub trait PolicyRevisionStore: UpsertRepository<Policy, String, Error = RepositoryError> {
+    /// Stores the policy as a new revision and makes it the current content of the policy,
+    /// if the current revision satisfies the precondition. The version of a policy is its current revision number.
+    async fn upsert_revision(
+        &self,
+        id: &str,
+        policy: Policy,
+        change: PolicyChange,
+        precondition: Precondition,
+    ) -> Result<PolicyRevision, RepositoryError>;
+
+    /// Lists the revisions of the policy, oldest first
+    async fn list_revisions(&self, id: &str) -> Result<Vec<PolicyRevision>, RepositoryError>;
+
+    /// Retrieves a revision of the policy
+    async fn get_revision(
+        &self,
+        id: &str,
+        revision: u64,
+    ) -> Result<PolicyRevision, RepositoryError>;
+}
+
+pub type PolicyRepository = dyn PolicyRevisionStore + Send + Sync;
@@ -0,0 +1,25 @@
+use crate::models::internal::refresh_token::RefreshToken;
+use crate::services::base::upsert_repository::RepositoryError;
+use async_trait::async_trait;
+
+#[async_trait]
+/// Represents a repository for the rotating refresh tokens
+pub trait RefreshTokenStore {
+    type Error;
+
+    /// Stores a new refresh token and drops the tokens that expired before it was issued
+    async fn insert(&self, token: RefreshToken) -> Result<(), Self::Error>;
+
+    /// Returns the stored token
+    async fn get_by_hash(&self, token_hash: &str) -> Result<RefreshToken, Self::Error>;
+
+    /// Marks the token as used and stores the token rotated from it, together.
+    /// Fails with `RepositoryError::PreconditionFailed` if the token has already been used,
+    /// so only one of the concurrent requests presenting the same token can rotate it
+    async fn rotate(&self, token_hash: &str, rotated: RefreshToken) -> Result<(), Self::Error>;
+
+    /// Deletes all tokens of the family
+    async fn delete_family(&self, family_id: &str) -> Result<(), Self::Error>;
+}
+
+pub type RefreshTokenRepository = dyn RefreshTokenStore<Error = RepositoryError> + Send + Sync;
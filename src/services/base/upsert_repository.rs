@@ -1,25 +1,234 @@
-use crate::models::external::identity::{ExternalIdentity, Policy, PolicyAttachment};
+use crate::models::external::identity::{ExternalIdentity, PolicyAttachment, Role};
 use async_trait::async_trait;
+use std::fmt::{Display, Formatter};
 
 #[async_trait]
 #[allow(dead_code)]
-/// Represents a repository for policies
-pub trait UpsertRepository<Entity, Key> {
+/// Represents a repository of entities identified by keys.
+/// Every change of an entity increments its version, so clients can detect concurrent changes.
+pub trait UpsertRepository<Entity: Send + 'static, Key: Send + 'static>: Sync {
     type Error;
 
-    /// Retrieves a policy by id
-    async fn get(&self, key: Key) -> Result<Entity, Self::Error>;
+    /// Retrieves the entity by key, with the version of its stored state.
+    /// Fails with `RepositoryError::NotFound` if the entity does not exist.
+    async fn get_versioned(&self, key: Key) -> Result<Versioned<Entity>, Self::Error>;
 
-    /// Updates or inserts a policy by id
-    async fn upsert(&self, key: Key, entity: Entity) -> Result<(), Self::Error>;
+    /// Updates or inserts the entity by key if its stored version satisfies the precondition, and returns the new version.
+    /// Fails with `RepositoryError::PreconditionFailed` otherwise, without changing the stored state.
+    /// A missing entity has no version, so it fails any `If-Match`.
+    async fn upsert_if(
+        &self,
+        key: Key,
+        entity: Entity,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error>;
 
-    /// Deletes policy by id
-    async fn delete(&self, key: Key) -> Result<(), Self::Error>;
+    /// Deletes the entity by key if its stored version satisfies the precondition.
+    /// Fails with `RepositoryError::PreconditionFailed` otherwise, and with `RepositoryError::NotFound`
+    /// if the entity does not exist and the precondition is satisfied.
+    async fn delete_if(&self, key: Key, precondition: Precondition) -> Result<(), Self::Error>;
+
+    /// Lists a page of the entities matching the filter, ordered by key
+    async fn list(&self, query: ListQuery<Key>) -> Result<Page<Key, Entity>, Self::Error>;
+
+    /// Retrieves the entity by key, regardless of its version
+    async fn get(&self, key: Key) -> Result<Entity, Self::Error> {
+        Ok(self.get_versioned(key).await?.entity)
+    }
+
+    /// Updates or inserts the entity by key unconditionally
+    async fn upsert(&self, key: Key, entity: Entity) -> Result<(), Self::Error> {
+        self.upsert_if(key, entity, Precondition::default()).await?;
+        Ok(())
+    }
+
+    /// Deletes the entity by key unconditionally
+    async fn delete(&self, key: Key) -> Result<(), Self::Error> {
+        self.delete_if(key, Precondition::default()).await
+    }
 }
-#[allow(dead_code)]
+
+/// An entity together with the version of its stored state
+#[derive(Debug, Clone)]
+pub struct Versioned<T> {
+    pub entity: T,
+
+    /// Starts at 1 when the entity is first created and is incremented by every change, including deletes,
+    /// so a recreated entity never has the version of a deleted one
+    pub version: u64,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(entity: T, version: u64) -> Self {
+        Versioned { entity, version }
+    }
+}
+
+/// Selects a page of the entities of a repository, ordered by key
+#[derive(Debug, Clone)]
+pub struct ListQuery<Key> {
+    /// The last key of the previous page, `None` for the first page
+    pub after: Option<Key>,
+
+    /// The maximum number of entities on the page
+    pub limit: usize,
+
+    pub filter: ListFilter,
+}
+
+/// Filters of the listed entities. The filters that do not apply to the entities of a repository are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListFilter {
+    /// Identities and attachments of the identity provider, and claim attachments of its claims
+    pub identity_provider: Option<String>,
+
+    /// Policies and roles whose id starts with the prefix, identities and attachments whose user id does
+    pub prefix: Option<String>,
+
+    /// Attachments and claim attachments of the policy
+    pub policy: Option<String>,
+
+    /// Attachments and claim attachments of the role
+    pub role: Option<String>,
+}
+
+/// A page of listed entities with their keys
+#[derive(Debug, Clone)]
+pub struct Page<Key, Entity> {
+    pub items: Vec<(Key, Entity)>,
+
+    /// Whether more entities follow the last one of the page
+    pub more: bool,
+}
+
+impl<Key, Entity> Page<Key, Entity> {
+    /// Creates a page from the entities following the previous page, fetching one more than the limit
+    /// tells whether another page follows
+    pub fn new(mut items: Vec<(Key, Entity)>, limit: usize) -> Self {
+        let more = items.len() > limit;
+        items.truncate(limit);
+        Page { items, more }
+    }
+}
+
+/// Versions accepted by a precondition, like the entity tags of the `If-Match` and `If-None-Match` headers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionMatch {
+    /// Any version of an existing entity
+    Any,
+
+    /// One of the versions
+    Versions(Vec<u64>),
+}
+
+impl VersionMatch {
+    fn matches(&self, current: Option<u64>) -> bool {
+        match (self, current) {
+            (_, None) => false,
+            (VersionMatch::Any, Some(_)) => true,
+            (VersionMatch::Versions(versions), Some(current)) => versions.contains(&current),
+        }
+    }
+}
+
+/// A condition on the stored state of an entity for a change to be applied.
+/// The default precondition is always satisfied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Precondition {
+    /// The entity must exist with one of the versions
+    pub if_match: Option<VersionMatch>,
+
+    /// The entity must not exist with any of the versions
+    pub if_none_match: Option<VersionMatch>,
+}
+
+impl Precondition {
+    /// Checks the precondition against the version of the stored entity, `None` if it does not exist
+    pub fn check(&self, current: Option<u64>) -> Result<(), RepositoryError> {
+        let if_match = match &self.if_match {
+            Some(expected) => expected.matches(current),
+            None => true,
+        };
+        let if_none_match = match &self.if_none_match {
+            Some(unexpected) => !unexpected.matches(current),
+            None => true,
+        };
+        match if_match && if_none_match {
+            true => Ok(()),
+            false => Err(RepositoryError::PreconditionFailed),
+        }
+    }
+}
+
+/// Errors returned by the repositories
+#[derive(Debug)]
+pub enum RepositoryError {
+    /// The requested entity does not exist
+    NotFound,
+
+    /// The entity conflicts with the stored state
+    Conflict(String),
+
+    /// The stored state does not satisfy the precondition of the change
+    PreconditionFailed,
+
+    /// The entity or the key is not valid
+    Invalid(String),
+
+    /// The storage backend failed or is unavailable
+    Backend(anyhow::Error),
+}
+
+impl Display for RepositoryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepositoryError::NotFound => write!(f, "Entity not found"),
+            RepositoryError::Conflict(reason) => write!(f, "Conflict: {}", reason),
+            RepositoryError::PreconditionFailed => {
+                write!(f, "Entity has been changed or does not exist")
+            }
+            RepositoryError::Invalid(reason) => write!(f, "Invalid entity: {}", reason),
+            RepositoryError::Backend(e) => write!(f, "Storage backend error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
 pub type IdentityRepository =
-    dyn UpsertRepository<ExternalIdentity, (String, String), Error = anyhow::Error> + Send + Sync;
-pub type PolicyRepository =
-    dyn UpsertRepository<Policy, String, Error = anyhow::Error> + Send + Sync;
+    dyn UpsertRepository<ExternalIdentity, (String, String), Error = RepositoryError> + Send + Sync;
+pub type RoleRepository = dyn UpsertRepository<Role, String, Error = RepositoryError> + Send + Sync;
 pub type PolicyAttachmentRepository =
-    dyn UpsertRepository<PolicyAttachment, ExternalIdentity, Error = anyhow::Error> + Send + Sync;
+    dyn UpsertRepository<PolicyAttachment, ExternalIdentity, Error = RepositoryError> + Send + Sync;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(None, None, None, true)]
+    #[case(None, None, Some(3), true)]
+    #[case(Some(VersionMatch::Versions(vec![3])), None, Some(3), true)]
+    #[case(Some(VersionMatch::Versions(vec![2, 3])), None, Some(3), true)]
+    #[case(Some(VersionMatch::Versions(vec![2])), None, Some(3), false)]
+    #[case(Some(VersionMatch::Versions(vec![3])), None, None, false)]
+    #[case(Some(VersionMatch::Any), None, Some(1), true)]
+    #[case(Some(VersionMatch::Any), None, None, false)]
+    #[case(None, Some(VersionMatch::Any), None, true)]
+    #[case(None, Some(VersionMatch::Any), Some(1), false)]
+    #[case(None, Some(VersionMatch::Versions(vec![2])), Some(3), true)]
+    #[case(None, Some(VersionMatch::Versions(vec![3])), Some(3), false)]
+    fn test_precondition(
+        #[case] if_match: Option<VersionMatch>,
+        #[case] if_none_match: Option<VersionMatch>,
+        #[case] current: Option<u64>,
+        #[case] satisfied: bool,
+    ) {
+        let precondition = Precondition {
+            if_match,
+            if_none_match,
+        };
+        assert_eq!(precondition.check(current).is_ok(), satisfied);
+    }
+}
@@ -1,53 +1,230 @@
 use crate::models::external::identity_provider::ExternalIdentityProvider;
 use crate::models::external::identity_provider_settings::OidcExternalIdentityProviderSettings;
+use crate::models::internal::admin_settings::AdminSettings;
+use crate::models::internal::policy_settings::PolicySettings;
+use crate::models::internal::settings::{RepositorySettings, Settings};
+use crate::models::internal::signing_key::{SigningKey, SigningKeyState};
+use crate::models::internal::token_settings::TokenSettings;
 use crate::services::identity_validator_provider::ExternalIdentityValidatorManager;
+use crate::services::signing_key_ring::SigningKeyManager;
+use anyhow::Context;
 use async_trait::async_trait;
 use log::{error, info};
+use std::collections::HashMap;
+use std::env;
 use std::sync::Arc;
 use tokio::time::sleep;
 
 #[async_trait]
 /// A trait for managing application configuration updates.
 pub trait ConfigurationManager {
-    /// Watches for IdentityProviderSettings update and upserts them into the identity validator provider.
+    /// Applies the identity provider settings to the identity validator provider:
+    /// added and changed providers are upserted, providers missing from the settings are removed.
+    /// Providers that cannot be built are logged and keep their previous validator.
+    async fn update_identity_providers(&self) -> Result<(), anyhow::Error>;
+
+    /// Watches for IdentityProviderSettings updates and applies them to the identity validator provider.
     async fn watch_for_identity_providers(self);
 
-    /// Reads the key for signing the issued tokens
-    fn get_signing_key(&self) -> Vec<u8>;
+    /// Reads the identity provider settings from the configuration file
+    fn get_identity_providers(
+        &self,
+    ) -> Result<
+        HashMap<ExternalIdentityProvider, OidcExternalIdentityProviderSettings>,
+        anyhow::Error,
+    >;
+
+    /// Reads the storage settings for the repositories from the configuration file
+    fn get_repository_settings(&self) -> Result<RepositorySettings, anyhow::Error>;
+
+    /// Reads the admin API authorization settings from the configuration file
+    fn get_admin_settings(&self) -> Result<Option<AdminSettings>, anyhow::Error>;
+
+    /// Reads the settings of the issued tokens from the configuration file
+    fn get_token_settings(&self) -> Result<TokenSettings, anyhow::Error>;
+
+    /// Reads the policy validation settings from the configuration file
+    fn get_policy_settings(&self) -> Result<PolicySettings, anyhow::Error>;
+
+    /// Watches for signing key ring updates and applies them to the signing key manager.
+    async fn watch_for_signing_keys(self, key_manager: Arc<dyn SigningKeyManager + Send + Sync>);
+
+    /// Reads the keys for signing the issued tokens together with their requested states
+    fn get_signing_keys(&self) -> Result<Vec<(SigningKey, SigningKeyState)>, anyhow::Error>;
 }
 
-/// Dummy implementation of the ConfigurationManager trait.
+/// File-based implementation of the ConfigurationManager trait.
 #[async_trait]
 impl<T> ConfigurationManager for Arc<T>
 where
     T: ExternalIdentityValidatorManager + Send + Sync,
 {
+    async fn update_identity_providers(&self) -> Result<(), anyhow::Error> {
+        let providers = self.get_identity_providers()?;
+        let current = self.get_settings().await;
+        let (updated, removed) = diff_identity_providers(&current, providers);
+        for (provider, settings) in updated {
+            match self.put(provider.clone(), settings).await {
+                Ok(_) => info!(
+                    "Successfully updated identity provider settings for {}",
+                    provider.name()
+                ),
+                Err(e) => error!(
+                    "Failed to initialize provider with name {}: {:?}",
+                    provider.name(),
+                    e
+                ),
+            }
+        }
+        for provider in removed {
+            match self.remove(provider.clone()).await {
+                Ok(_) => info!("Removed identity provider {}", provider.name()),
+                Err(e) => error!(
+                    "Failed to remove provider with name {}: {:?}",
+                    provider.name(),
+                    e
+                ),
+            }
+        }
+        Ok(())
+    }
+
     async fn watch_for_identity_providers(self) {
-        let provider = ExternalIdentityProvider::from("provider".to_string());
-        let settings = OidcExternalIdentityProviderSettings {
-            user_id_claim: "upn".to_string(),
-            discovery_url: "https://sts.windows.net/06152121-b4c5-4544-abf5-9268e75db448/"
-                .to_string(),
-            issuers: vec![
-                "https://sts.windows.net/06152121-b4c5-4544-abf5-9268e75db448/".to_string(),
-            ],
-            audiences: vec!["https://management.core.windows.net/".to_string()],
-        };
-        let result = self.put(provider.clone(), settings).await;
-        match result {
-            Ok(_) => info!("Successfully updated identity provider settings"),
-            Err(e) => error!(
-                "Failed to initialize provider with name {}: {:?}",
-                provider.name(),
-                e
-            ),
+        loop {
+            sleep(std::time::Duration::from_secs(10)).await;
+            if let Err(e) = self.update_identity_providers().await {
+                error!("Failed to read identity provider settings: {:?}", e);
+            }
         }
+    }
+
+    fn get_identity_providers(
+        &self,
+    ) -> Result<
+        HashMap<ExternalIdentityProvider, OidcExternalIdentityProviderSettings>,
+        anyhow::Error,
+    > {
+        Ok(read_settings()?.identity_providers())
+    }
+
+    fn get_repository_settings(&self) -> Result<RepositorySettings, anyhow::Error> {
+        Ok(read_settings()?.repository)
+    }
+
+    fn get_admin_settings(&self) -> Result<Option<AdminSettings>, anyhow::Error> {
+        Ok(read_settings()?.admin)
+    }
+
+    fn get_token_settings(&self) -> Result<TokenSettings, anyhow::Error> {
+        Ok(read_settings()?.token)
+    }
+
+    fn get_policy_settings(&self) -> Result<PolicySettings, anyhow::Error> {
+        Ok(read_settings()?.policy)
+    }
+
+    async fn watch_for_signing_keys(self, key_manager: Arc<dyn SigningKeyManager + Send + Sync>) {
         loop {
             sleep(std::time::Duration::from_secs(10)).await;
+            let result = match self.get_signing_keys() {
+                Ok(keys) => key_manager.update(keys).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!("Failed to update signing keys: {:?}", e);
+            }
+        }
+    }
+
+    fn get_signing_keys(&self) -> Result<Vec<(SigningKey, SigningKeyState)>, anyhow::Error> {
+        read_settings()?
+            .signing_keys
+            .into_iter()
+            .map(|s| {
+                let pem = std::fs::read(&s.path).with_context(|| {
+                    format!("Failed to read signing key {} from {}", s.kid, s.path)
+                })?;
+                let key = SigningKey::from_pem(s.kid.clone(), s.algorithm, &pem)
+                    .with_context(|| format!("Failed to load signing key {}", s.kid))?;
+                Ok((key, s.state))
+            })
+            .collect()
+    }
+}
+
+fn read_settings() -> Result<Settings, anyhow::Error> {
+    let path = env::var("BOXER_CONFIG_PATH").context("BOXER_CONFIG_PATH is not set")?;
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read configuration from {}", path))?;
+    Settings::from_yaml(&content).with_context(|| format!("Invalid configuration in {}", path))
+}
+
+/// Compares the registered identity providers with the desired ones.
+/// Returns the providers that have to be added or updated, and the providers that have to be removed.
+fn diff_identity_providers(
+    current: &HashMap<ExternalIdentityProvider, OidcExternalIdentityProviderSettings>,
+    desired: HashMap<ExternalIdentityProvider, OidcExternalIdentityProviderSettings>,
+) -> (
+    Vec<(
+        ExternalIdentityProvider,
+        OidcExternalIdentityProviderSettings,
+    )>,
+    Vec<ExternalIdentityProvider>,
+) {
+    let removed = current
+        .keys()
+        .filter(|provider| !desired.contains_key(provider))
+        .cloned()
+        .collect();
+    let updated = desired
+        .into_iter()
+        .filter(|(provider, settings)| current.get(provider) != Some(settings))
+        .collect();
+    (updated, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn settings(user_id_claim: &str) -> OidcExternalIdentityProviderSettings {
+        OidcExternalIdentityProviderSettings {
+            user_id_claim: user_id_claim.to_string(),
+            discovery_url: "https://example.com/".to_string(),
+            issuers: vec!["https://example.com/".to_string()],
+            audiences: vec!["audience".to_string()],
+            token: None,
         }
     }
 
-    fn get_signing_key(&self) -> Vec<u8> {
-        vec!["dummy-secret".as_bytes()].concat()
+    fn providers(
+        entries: &[(&str, &str)],
+    ) -> HashMap<ExternalIdentityProvider, OidcExternalIdentityProviderSettings> {
+        entries
+            .iter()
+            .map(|(name, claim)| {
+                (
+                    ExternalIdentityProvider::from(name.to_string()),
+                    settings(claim),
+                )
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn test_diff_identity_providers() {
+        let current = providers(&[("unchanged", "upn"), ("changed", "upn"), ("removed", "upn")]);
+        let desired = providers(&[("unchanged", "upn"), ("changed", "email"), ("added", "upn")]);
+
+        let (updated, removed) = diff_identity_providers(&current, desired);
+
+        let mut updated: Vec<String> = updated.iter().map(|(p, _)| p.name()).collect();
+        updated.sort();
+        assert_eq!(updated, vec!["added", "changed"]);
+        assert_eq!(
+            removed,
+            vec![ExternalIdentityProvider::from("removed".to_string())]
+        );
     }
 }
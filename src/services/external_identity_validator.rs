@@ -9,12 +9,26 @@ use log::info;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The identity extracted from a validated external token.
+#[derive(Debug, Clone)]
+pub struct ValidatedIdentity {
+    /// The external identity of the token owner
+    pub identity: ExternalIdentity,
+
+    /// The expiration time of the external token, if present
+    pub expires_at: Option<SystemTime>,
+
+    /// The validated claims of the external token
+    pub claims: DynamicClaimsCollection,
+}
 
 /// Validator for external identity.
 #[async_trait]
 pub trait ExternalIdentityValidator {
     /// Validate the external identity token and return the external identity.
-    async fn validate(&self, token: ExternalToken) -> Result<ExternalIdentity, anyhow::Error>;
+    async fn validate(&self, token: ExternalToken) -> Result<ValidatedIdentity, anyhow::Error>;
 }
 
 /// Instantiates a new external identity validator with given name and settings.
@@ -39,7 +53,7 @@ struct ExternalIdentityValidatorImpl {
 
 #[async_trait]
 impl ExternalIdentityValidator for ExternalIdentityValidatorImpl {
-    async fn validate(&self, token: ExternalToken) -> Result<ExternalIdentity, anyhow::Error> {
+    async fn validate(&self, token: ExternalToken) -> Result<ValidatedIdentity, anyhow::Error> {
         let token_str: String = token.into();
         let result = self.authorizer.check_auth(&token_str).await?;
         let maybe_ext_id = extract_user_id(&result.claims, &self.user_id_claim, self.name.clone());
@@ -49,7 +63,16 @@ impl ExternalIdentityValidator for ExternalIdentityValidatorImpl {
                     "Successfully validated token for user {}/{}",
                     ext_id.user_id, self.name
                 );
-                Ok(ext_id)
+                let expires_at = result
+                    .claims
+                    .get("exp")
+                    .and_then(|exp| exp.as_u64())
+                    .map(|exp| UNIX_EPOCH + Duration::from_secs(exp));
+                Ok(ValidatedIdentity {
+                    identity: ext_id,
+                    expires_at,
+                    claims: result.claims,
+                })
             }
             None => bail!("Failed to extract user id from token"),
         }
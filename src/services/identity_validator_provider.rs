@@ -17,11 +17,21 @@ pub fn new() -> ExternalIdentityValidationService {
 /// Write-only interface for managing external identity validators.
 #[async_trait]
 pub trait ExternalIdentityValidatorManager {
+    /// Builds a validator from the settings and registers it for the provider.
+    /// If the validator cannot be built, the previously registered validator is kept.
     async fn put(
         &self,
         provider: ExternalIdentityProvider,
         settings: OidcExternalIdentityProviderSettings,
     ) -> Result<(), anyhow::Error>;
+
+    /// Unregisters the validator of the provider.
+    async fn remove(&self, provider: ExternalIdentityProvider) -> Result<(), anyhow::Error>;
+
+    /// Returns the settings of the currently registered validators.
+    async fn get_settings(
+        &self,
+    ) -> HashMap<ExternalIdentityProvider, OidcExternalIdentityProviderSettings>;
 }
 
 /// Read-only interface for managing external identity validators.
@@ -31,11 +41,24 @@ pub trait ExternalIdentityValidatorProvider {
         &self,
         provider: ExternalIdentityProvider,
     ) -> Result<Arc<dyn ExternalIdentityValidator + Send + Sync>, anyhow::Error>;
+
+    /// Returns the settings the validator of the provider was built with.
+    async fn get_provider_settings(
+        &self,
+        provider: ExternalIdentityProvider,
+    ) -> Result<OidcExternalIdentityProviderSettings, anyhow::Error>;
+
+    /// Returns the providers that accept tokens from the issuer.
+    async fn find_by_issuer(&self, issuer: &str) -> Vec<ExternalIdentityProvider>;
+}
+
+struct RegisteredValidator {
+    settings: OidcExternalIdentityProviderSettings,
+    validator: Arc<dyn ExternalIdentityValidator + Send + Sync>,
 }
 
 pub struct ExternalIdentityValidationService {
-    validators:
-        RwLock<HashMap<ExternalIdentityProvider, Arc<dyn ExternalIdentityValidator + Send + Sync>>>,
+    validators: RwLock<HashMap<ExternalIdentityProvider, RegisteredValidator>>,
 }
 
 #[async_trait]
@@ -46,10 +69,30 @@ impl ExternalIdentityValidatorProvider for ExternalIdentityValidationService {
     ) -> Result<Arc<dyn ExternalIdentityValidator + Send + Sync>, anyhow::Error> {
         let read_guard = self.validators.read().await;
         match (*read_guard).get(&provider) {
-            Some(validator) => Ok(Arc::clone(validator)),
+            Some(registered) => Ok(Arc::clone(&registered.validator)),
             None => bail!("Could not find validator for provider: {}", provider.name()),
         }
     }
+
+    async fn get_provider_settings(
+        &self,
+        provider: ExternalIdentityProvider,
+    ) -> Result<OidcExternalIdentityProviderSettings, anyhow::Error> {
+        let read_guard = self.validators.read().await;
+        match (*read_guard).get(&provider) {
+            Some(registered) => Ok(registered.settings.clone()),
+            None => bail!("Could not find validator for provider: {}", provider.name()),
+        }
+    }
+
+    async fn find_by_issuer(&self, issuer: &str) -> Vec<ExternalIdentityProvider> {
+        let read_guard = self.validators.read().await;
+        (*read_guard)
+            .iter()
+            .filter(|(_, registered)| registered.settings.issuers.iter().any(|i| i == issuer))
+            .map(|(provider, _)| provider.clone())
+            .collect()
+    }
 }
 
 #[async_trait]
@@ -59,11 +102,36 @@ impl ExternalIdentityValidatorManager for ExternalIdentityValidationService {
         provider: ExternalIdentityProvider,
         settings: OidcExternalIdentityProviderSettings,
     ) -> Result<(), anyhow::Error> {
+        // Requests in flight keep their own reference to the previous validator
+        let validator = settings.clone().build_validator(provider.name()).await?;
         let mut write_guard = self.validators.write().await;
-        let validator = settings.build_validator(provider.name()).await?;
-        let _ = (*write_guard).insert(provider, validator);
+        let _ = (*write_guard).insert(
+            provider,
+            RegisteredValidator {
+                settings,
+                validator,
+            },
+        );
         Ok(())
     }
+
+    async fn remove(&self, provider: ExternalIdentityProvider) -> Result<(), anyhow::Error> {
+        let mut write_guard = self.validators.write().await;
+        match (*write_guard).remove(&provider) {
+            Some(_) => Ok(()),
+            None => bail!("Could not find validator for provider: {}", provider.name()),
+        }
+    }
+
+    async fn get_settings(
+        &self,
+    ) -> HashMap<ExternalIdentityProvider, OidcExternalIdentityProviderSettings> {
+        let read_guard = self.validators.read().await;
+        (*read_guard)
+            .iter()
+            .map(|(provider, registered)| (provider.clone(), registered.settings.clone()))
+            .collect()
+    }
 }
 
 impl ExternalIdentityValidationService {
@@ -72,3 +140,23 @@ impl ExternalIdentityValidationService {
         ExternalIdentityValidationService { validators }
     }
 }
+
+#[cfg(test)]
+impl ExternalIdentityValidationService {
+    /// Registers a prebuilt validator, without fetching the metadata of the identity provider
+    pub(crate) async fn register(
+        &self,
+        provider: ExternalIdentityProvider,
+        settings: OidcExternalIdentityProviderSettings,
+        validator: Arc<dyn ExternalIdentityValidator + Send + Sync>,
+    ) {
+        let mut write_guard = self.validators.write().await;
+        (*write_guard).insert(
+            provider,
+            RegisteredValidator {
+                settings,
+                validator,
+            },
+        );
+    }
+}
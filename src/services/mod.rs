@@ -1,7 +1,12 @@
+pub mod admin_authorizer;
 pub mod base;
 /// This module contains services abstracted from the Actix web server.
 pub mod configuration_manager;
 pub mod external_identity_validator;
 pub mod identity_validator_provider;
+pub mod policy_evaluator;
+pub mod policy_validator;
 pub mod repositories;
+pub mod signing_key_ring;
+pub mod token_introspector;
 pub mod token_service;
@@ -0,0 +1,172 @@
+use crate::models::external::identity::{ExternalIdentity, Policy};
+use crate::models::internal::evaluation::{
+    EvaluationDecision, EvaluationRequest, EvaluationResponse,
+};
+use crate::services::base::upsert_repository::RepositoryError;
+use crate::services::token_service::{TokenProvider, TokenService};
+use async_trait::async_trait;
+use cedar_policy::{
+    Authorizer, Context, Decision, Entities, EntityUid, PolicyId, PolicySet, Request,
+};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Creates a new policy evaluation service.
+pub fn new(token_provider: Arc<TokenService>) -> PolicyEvaluationService {
+    PolicyEvaluationService { token_provider }
+}
+
+/// Evaluates authorization requests against the policies of an identity, to debug access without issuing tokens.
+#[async_trait]
+pub trait PolicyEvaluator {
+    /// Evaluates the request against the policies a token issued to the identity would carry.
+    async fn evaluate(
+        &self,
+        request: EvaluationRequest,
+    ) -> Result<EvaluationResponse, RepositoryError>;
+}
+
+pub struct PolicyEvaluationService {
+    token_provider: Arc<TokenService>,
+}
+
+#[async_trait]
+impl PolicyEvaluator for PolicyEvaluationService {
+    async fn evaluate(
+        &self,
+        request: EvaluationRequest,
+    ) -> Result<EvaluationResponse, RepositoryError> {
+        let identity = ExternalIdentity::new(request.identity_provider, request.user_id);
+        let policies = self
+            .token_provider
+            .resolve_policies(&identity, &request.claims, None)
+            .await
+            .map_err(|e| match e.downcast::<RepositoryError>() {
+                Ok(e) => e,
+                Err(e) => RepositoryError::Invalid(e.to_string()),
+            })?;
+
+        let principal = parse_entity_uid("principal", &request.principal)?;
+        let action = parse_entity_uid("action", &request.action)?;
+        let resource = parse_entity_uid("resource", &request.resource)?;
+        let context = Context::from_json_value(request.context.unwrap_or(json!({})), None)
+            .map_err(|e| RepositoryError::Invalid(format!("Invalid context: {}", e)))?;
+        let entities = Entities::from_json_value(request.entities.unwrap_or(json!([])), None)
+            .map_err(|e| RepositoryError::Invalid(format!("Invalid entities: {}", e)))?;
+
+        let (policy_set, policy_ids, mut errors) = build_policy_set(&policies);
+        let cedar_request = Request::new(Some(principal), Some(action), Some(resource), context);
+        let response = Authorizer::new().is_authorized(&cedar_request, &policy_set, &entities);
+
+        let determining_policies: BTreeSet<String> = response
+            .diagnostics()
+            .reason()
+            .filter_map(|id| policy_ids.get(id.to_string().as_str()).cloned())
+            .collect();
+        errors.extend(response.diagnostics().errors().map(|e| e.to_string()));
+        Ok(EvaluationResponse {
+            decision: match response.decision() {
+                Decision::Allow => EvaluationDecision::Allow,
+                Decision::Deny => EvaluationDecision::Deny,
+            },
+            policies: policies.into_keys().collect(),
+            determining_policies: determining_policies.into_iter().collect(),
+            errors,
+        })
+    }
+}
+
+fn parse_entity_uid(name: &str, value: &str) -> Result<EntityUid, RepositoryError> {
+    EntityUid::from_str(value)
+        .map_err(|e| RepositoryError::Invalid(format!("Invalid {} '{}': {}", name, value, e)))
+}
+
+/// Merges the policies into a single Cedar policy set, like the consumers of the issued tokens do.
+/// Each Cedar policy gets the id `<policy id>#<index>`, so the determining policies can be traced back.
+/// Returns the policy set, the policy ids by Cedar policy id, and the policies that could not be parsed.
+fn build_policy_set(
+    policies: &BTreeMap<String, Policy>,
+) -> (PolicySet, HashMap<String, String>, Vec<String>) {
+    let mut policy_set = PolicySet::new();
+    let mut policy_ids = HashMap::new();
+    let mut errors = Vec::new();
+    for (id, policy) in policies {
+        let parsed = match PolicySet::from_str(&policy.content) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                errors.push(format!("Policy {} is not valid Cedar: {}", id, e));
+                continue;
+            }
+        };
+        for (index, cedar_policy) in parsed.policies().enumerate() {
+            let cedar_id = format!("{}#{}", id, index);
+            // PolicyId parsing never fails
+            let Ok(policy_id) = PolicyId::from_str(&cedar_id) else {
+                continue;
+            };
+            if let Err(e) = policy_set.add(cedar_policy.new_id(policy_id)) {
+                errors.push(format!("Policy {} cannot be merged: {}", id, e));
+                continue;
+            }
+            policy_ids.insert(cedar_id, id.clone());
+        }
+    }
+    (policy_set, policy_ids, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn evaluate(policies: &[(&str, &str)], principal: &str) -> (Decision, Vec<String>, usize) {
+        let policies: BTreeMap<String, Policy> = policies
+            .iter()
+            .map(|(id, content)| (id.to_string(), Policy::new(content.to_string())))
+            .collect();
+        let (policy_set, policy_ids, errors) = build_policy_set(&policies);
+        let request = Request::new(
+            Some(EntityUid::from_str(principal).unwrap()),
+            Some(EntityUid::from_str(r#"Action::"read""#).unwrap()),
+            Some(EntityUid::from_str(r#"Document::"report""#).unwrap()),
+            Context::empty(),
+        );
+        let response = Authorizer::new().is_authorized(&request, &policy_set, &Entities::empty());
+        let mut determining: Vec<String> = response
+            .diagnostics()
+            .reason()
+            .map(|id| policy_ids[id.to_string().as_str()].clone())
+            .collect();
+        determining.sort();
+        (response.decision(), determining, errors.len())
+    }
+
+    #[rstest]
+    #[case(r#"User::"alice""#, Decision::Allow, vec!["readers"])]
+    #[case(r#"User::"bob""#, Decision::Deny, vec!["blocked"])]
+    #[case(r#"User::"carol""#, Decision::Deny, vec![])]
+    fn test_determining_policies(
+        #[case] principal: &str,
+        #[case] decision: Decision,
+        #[case] determining: Vec<&str>,
+    ) {
+        let policies = [
+            (
+                "readers",
+                r#"permit(principal == User::"alice", action, resource);
+                   permit(principal == User::"bob", action, resource);"#,
+            ),
+            (
+                "blocked",
+                r#"forbid(principal == User::"bob", action, resource);"#,
+            ),
+            ("invalid", "permit(principal, action, resource)"),
+        ];
+        let (actual_decision, actual_determining, errors) = evaluate(&policies, principal);
+        assert_eq!(actual_decision, decision);
+        assert_eq!(actual_determining, determining);
+        assert_eq!(errors, 1);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::models::external::identity::Policy;
+use crate::models::external::policy_template;
+use crate::models::internal::policy_settings::PolicySettings;
+use crate::services::base::upsert_repository::RepositoryError;
+use anyhow::Context;
+use cedar_policy::{PolicySet, Schema, ValidationMode, Validator};
+use std::fs::File;
+use std::str::FromStr;
+
+/// Creates a new policy validator, loading the configured Cedar schema.
+pub fn new(settings: &PolicySettings) -> Result<CedarPolicyValidator, anyhow::Error> {
+    let validator = match &settings.schema_path {
+        Some(path) => {
+            let file = File::open(path)
+                .with_context(|| format!("Failed to read Cedar schema from {}", path))?;
+            let schema = Schema::from_file(file)
+                .with_context(|| format!("Failed to parse Cedar schema from {}", path))?;
+            Some(Validator::new(schema))
+        }
+        None => None,
+    };
+    Ok(CedarPolicyValidator { validator })
+}
+
+/// Checks the policies before they are stored.
+pub trait PolicyValidator {
+    /// Returns `RepositoryError::Invalid` with the diagnostics if the policy is not valid.
+    fn validate(&self, policy: &Policy) -> Result<(), RepositoryError>;
+}
+
+/// Parses the policies as Cedar and validates them against the schema, if one is configured.
+pub struct CedarPolicyValidator {
+    validator: Option<Validator>,
+}
+
+impl PolicyValidator for CedarPolicyValidator {
+    fn validate(&self, policy: &Policy) -> Result<(), RepositoryError> {
+        let content = match policy.template {
+            // Variables outside of string literals would be rendered as an escaped quote and fail to parse
+            true => policy_template::render(&policy.content, |_| Ok("\"".to_string()))
+                .map_err(|e| RepositoryError::Invalid(format!("Invalid policy template: {}", e)))?,
+            false => policy.content.clone(),
+        };
+        let policy_set = PolicySet::from_str(&content).map_err(|e| {
+            let hint = match policy.template {
+                true => " (template variables must be placed inside string literals)",
+                false => "",
+            };
+            RepositoryError::Invalid(format!(
+                "Failed to parse Cedar policy{}: {}",
+                hint,
+                e.errors_as_strings().join("; ")
+            ))
+        })?;
+        if policy_set.templates().next().is_some() {
+            return Err(RepositoryError::Invalid(
+                "Cedar templates with slots are not supported".to_string(),
+            ));
+        }
+        if policy_set.policies().next().is_none() {
+            return Err(RepositoryError::Invalid(
+                "Policy does not contain any Cedar policies".to_string(),
+            ));
+        }
+        let Some(validator) = &self.validator else {
+            return Ok(());
+        };
+        let result = validator.validate(&policy_set, ValidationMode::default());
+        if result.validation_passed() {
+            return Ok(());
+        }
+        let errors: Vec<String> = result.validation_errors().map(|e| e.to_string()).collect();
+        Err(RepositoryError::Invalid(format!(
+            "Policy does not match the Cedar schema: {}",
+            errors.join("; ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn validator(schema: Option<serde_json::Value>) -> CedarPolicyValidator {
+        CedarPolicyValidator {
+            validator: schema.map(|s| Validator::new(Schema::from_json_value(s).unwrap())),
+        }
+    }
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "": {
+                "entityTypes": {
+                    "User": {},
+                    "Document": {}
+                },
+                "actions": {
+                    "read": {
+                        "appliesTo": {
+                            "principalTypes": ["User"],
+                            "resourceTypes": ["Document"]
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    #[rstest]
+    #[case("permit(principal, action, resource);", true)]
+    #[case("permit(principal, action, resource)", false)]
+    #[case("permit(principal == ?principal, action, resource);", false)]
+    #[case("", false)]
+    #[case("// only a comment", false)]
+    fn test_policy_is_parsed(#[case] content: &str, #[case] valid: bool) {
+        let result = validator(None).validate(&Policy::new(content.to_string()));
+        assert_eq!(result.is_ok(), valid, "{:?}", result);
+    }
+
+    #[rstest]
+    #[case(r#"permit(principal == User::"{{user_id}}", action, resource);"#, true)]
+    #[case(
+        r#"permit(principal, action, resource) when { context.tid == "{{claims.tid}}" };"#,
+        true
+    )]
+    #[case(r#"permit(principal == User::{{user_id}}, action, resource);"#, false)]
+    #[case(
+        r#"permit(principal, action, resource) when { {{claims.condition}} };"#,
+        false
+    )]
+    #[case(
+        r#"permit(principal == User::"{{unknown}}", action, resource);"#,
+        false
+    )]
+    fn test_template_is_parsed(#[case] content: &str, #[case] valid: bool) {
+        let result = validator(None).validate(&Policy::template(content.to_string()));
+        assert_eq!(result.is_ok(), valid, "{:?}", result);
+    }
+
+    #[rstest]
+    #[case(r#"permit(principal, action == Action::"read", resource);"#, true)]
+    #[case(r#"permit(principal, action == Action::"write", resource);"#, false)]
+    #[case(r#"permit(principal == Group::"admins", action, resource);"#, false)]
+    fn test_policy_is_validated_against_schema(#[case] content: &str, #[case] valid: bool) {
+        let result = validator(Some(schema())).validate(&Policy::new(content.to_string()));
+        assert_eq!(result.is_ok(), valid, "{:?}", result);
+    }
+}
@@ -1,104 +1,569 @@
-use crate::models::external::identity::{ExternalIdentity, Policy, PolicyAttachment};
-use crate::services::base::upsert_repository::UpsertRepository;
-use anyhow::bail;
+use crate::models::external::claim_attachment::{ClaimAttachment, ClaimAttachmentKey};
+use crate::models::external::identity::{ExternalIdentity, Policy, PolicyAttachment, Role};
+use crate::models::external::policy_revision::{PolicyChange, PolicyRevision};
+use crate::models::internal::refresh_token::RefreshToken;
+use crate::models::internal::revocation::{Revocation, RevocationTarget};
+use crate::services::base::claim_attachment_store::ClaimAttachmentStore;
+use crate::services::base::expiring_repository::ExpiringRepository;
+use crate::services::base::policy_revision_store::PolicyRevisionStore;
+use crate::services::base::refresh_token_store::RefreshTokenStore;
+use crate::services::base::upsert_repository::{
+    ListFilter, ListQuery, Page, Precondition, RepositoryError, UpsertRepository, Versioned,
+};
+use crate::services::repositories::group_claim_attachments;
 use async_trait::async_trait;
 use std::collections::HashMap;
+use std::hash::Hash;
 use tokio::sync::RwLock;
 
+/// The stored entities with their versions. A deleted entity is kept as `None`, so that a recreated entity never reuses
+/// the version of the deleted one
+type Entries<K, T> = HashMap<K, Versioned<Option<T>>>;
+
+/// Returns the stored entity with its version
+fn get_entry<K: Eq + Hash, T: Clone>(
+    map: &Entries<K, T>,
+    key: &K,
+) -> Result<Versioned<T>, RepositoryError> {
+    match map.get(key) {
+        Some(Versioned {
+            entity: Some(entity),
+            version,
+        }) => Ok(Versioned::new(entity.clone(), *version)),
+        _ => Err(RepositoryError::NotFound),
+    }
+}
+
+/// Returns the version of the stored entity, `None` if it does not exist
+fn current_version<K: Eq + Hash, T>(map: &Entries<K, T>, key: &K) -> Option<u64> {
+    map.get(key)
+        .filter(|stored| stored.entity.is_some())
+        .map(|stored| stored.version)
+}
+
+/// Returns the stored entities that are not deleted
+fn existing<K, T>(map: &Entries<K, T>) -> impl Iterator<Item = (&K, &T)> {
+    map.iter()
+        .filter_map(|(key, stored)| Some((key, stored.entity.as_ref()?)))
+}
+
+/// Replaces the stored entity with the result of `update` if its version satisfies the precondition,
+/// and returns the new version
+fn update_entry<K: Eq + Hash, T>(
+    map: &mut Entries<K, T>,
+    key: K,
+    precondition: Precondition,
+    update: impl FnOnce(Option<T>) -> T,
+) -> Result<u64, RepositoryError> {
+    precondition.check(current_version(map, &key))?;
+    let stored = map.entry(key).or_insert(Versioned::new(None, 0));
+    stored.entity = Some(update(stored.entity.take()));
+    stored.version += 1;
+    Ok(stored.version)
+}
+
+/// Removes the stored entity if its version satisfies the precondition, keeping its version
+fn remove_entry<K: Eq + Hash, T>(
+    map: &mut Entries<K, T>,
+    key: &K,
+    precondition: Precondition,
+) -> Result<(), RepositoryError> {
+    precondition.check(current_version(map, key))?;
+    match map.get_mut(key) {
+        Some(stored) if stored.entity.is_some() => {
+            stored.entity = None;
+            stored.version += 1;
+            Ok(())
+        }
+        _ => Err(RepositoryError::NotFound),
+    }
+}
+
+/// Selects the page of the entries after the last key of the previous page, in the order of `sort_key`
+fn page_of<K, T, S: Ord>(
+    entries: impl IntoIterator<Item = (K, T)>,
+    query: &ListQuery<K>,
+    sort_key: impl Fn(&K) -> S,
+) -> Page<K, T> {
+    let after = query.after.as_ref().map(&sort_key);
+    let mut items: Vec<(K, T)> = entries
+        .into_iter()
+        .filter(|(key, _)| after.as_ref().is_none_or(|after| sort_key(key) > *after))
+        .collect();
+    items.sort_by_key(|(key, _)| sort_key(key));
+    items.truncate(query.limit + 1);
+    Page::new(items, query.limit)
+}
+
+/// Normalizes the key of an identity like `ExternalIdentity` does, so that keys differing in case match the same identity
+fn identity_key(key: (String, String)) -> (String, String) {
+    let identity = ExternalIdentity::from(key);
+    (identity.identity_provider, identity.user_id)
+}
+
+fn matches_prefix(filter: &ListFilter, id: &str) -> bool {
+    filter
+        .prefix
+        .as_ref()
+        .is_none_or(|prefix| id.starts_with(prefix.as_str()))
+}
+
+fn matches_identity_provider(filter: &ListFilter, identity_provider: &str) -> bool {
+    filter
+        .identity_provider
+        .as_ref()
+        .is_none_or(|expected| expected == identity_provider)
+}
+
+fn matches_attachment(filter: &ListFilter, attachment: &PolicyAttachment) -> bool {
+    filter
+        .policy
+        .as_ref()
+        .is_none_or(|policy| attachment.policies.contains(policy))
+        && filter
+            .role
+            .as_ref()
+            .is_none_or(|role| attachment.roles.contains(role))
+}
+
 #[async_trait]
 impl UpsertRepository<ExternalIdentity, (String, String)>
-    for RwLock<HashMap<(String, String), ExternalIdentity>>
+    for RwLock<Entries<(String, String), ExternalIdentity>>
 {
-    type Error = anyhow::Error;
+    type Error = RepositoryError;
 
-    async fn get(&self, key: (String, String)) -> Result<ExternalIdentity, Self::Error> {
+    async fn get_versioned(
+        &self,
+        key: (String, String),
+    ) -> Result<Versioned<ExternalIdentity>, Self::Error> {
         let read_guard = self.read().await;
-        match (*read_guard).get(&key) {
-            Some(entity) => Ok(entity.clone()),
-            None => bail!("Entity not found"),
-        }
+        get_entry(&read_guard, &identity_key(key))
     }
 
-    async fn upsert(
+    async fn upsert_if(
         &self,
         key: (String, String),
         entity: ExternalIdentity,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
+        let mut write_guard = self.write().await;
+        update_entry(&mut write_guard, identity_key(key), precondition, |_| {
+            entity
+        })
+    }
+
+    async fn delete_if(
+        &self,
+        key: (String, String),
+        precondition: Precondition,
     ) -> Result<(), Self::Error> {
         let mut write_guard = self.write().await;
-        (*write_guard).insert(key, entity);
-        Ok(())
+        remove_entry(&mut write_guard, &identity_key(key), precondition)
     }
 
-    async fn delete(&self, key: (String, String)) -> Result<(), Self::Error> {
+    async fn list(
+        &self,
+        query: ListQuery<(String, String)>,
+    ) -> Result<Page<(String, String), ExternalIdentity>, Self::Error> {
+        let read_guard = self.read().await;
+        let entries = existing(&read_guard)
+            .filter(|(_, identity)| {
+                matches_identity_provider(&query.filter, &identity.identity_provider)
+                    && matches_prefix(&query.filter, &identity.user_id)
+            })
+            .map(|(key, identity)| (key.clone(), identity.clone()));
+        Ok(page_of(entries, &query, |key| identity_key(key.clone())))
+    }
+}
+
+/// The revisions of a policy, the last one being the current content unless the policy is deleted
+#[derive(Default)]
+pub struct PolicyHistory {
+    revisions: Vec<PolicyRevision>,
+    deleted: bool,
+}
+
+impl PolicyHistory {
+    /// The current revision, if the policy exists
+    fn current(&self) -> Option<&PolicyRevision> {
+        match self.deleted {
+            true => None,
+            false => self.revisions.last(),
+        }
+    }
+}
+
+#[async_trait]
+impl UpsertRepository<Policy, String> for RwLock<HashMap<String, PolicyHistory>> {
+    type Error = RepositoryError;
+
+    async fn get_versioned(&self, key: String) -> Result<Versioned<Policy>, Self::Error> {
+        let read_guard = self.read().await;
+        match (*read_guard)
+            .get(&key)
+            .and_then(|history| history.current())
+        {
+            Some(current) => Ok(Versioned::new(current.policy.clone(), current.revision)),
+            None => Err(RepositoryError::NotFound),
+        }
+    }
+
+    async fn upsert_if(
+        &self,
+        key: String,
+        entity: Policy,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
+        let revision = self
+            .upsert_revision(&key, entity, PolicyChange::default(), precondition)
+            .await?;
+        Ok(revision.revision)
+    }
+
+    async fn delete_if(&self, key: String, precondition: Precondition) -> Result<(), Self::Error> {
         let mut write_guard = self.write().await;
-        (*write_guard).remove(&key);
-        Ok(())
+        let history = (*write_guard).get_mut(&key);
+        let current = history
+            .as_ref()
+            .and_then(|history| history.current())
+            .map(|current| current.revision);
+        precondition.check(current)?;
+        match (history, current) {
+            (Some(history), Some(_)) => {
+                history.deleted = true;
+                Ok(())
+            }
+            _ => Err(RepositoryError::NotFound),
+        }
+    }
+
+    async fn list(&self, query: ListQuery<String>) -> Result<Page<String, Policy>, Self::Error> {
+        let read_guard = self.read().await;
+        let entries = (*read_guard)
+            .iter()
+            .filter(|(id, _)| matches_prefix(&query.filter, id))
+            .filter_map(|(id, history)| Some((id.clone(), history.current()?.policy.clone())));
+        Ok(page_of(entries, &query, |id| id.clone()))
     }
 }
 
 #[async_trait]
-impl UpsertRepository<Policy, String> for RwLock<HashMap<String, Policy>> {
-    type Error = anyhow::Error;
+impl PolicyRevisionStore for RwLock<HashMap<String, PolicyHistory>> {
+    async fn upsert_revision(
+        &self,
+        id: &str,
+        policy: Policy,
+        change: PolicyChange,
+        precondition: Precondition,
+    ) -> Result<PolicyRevision, RepositoryError> {
+        let mut write_guard = self.write().await;
+        let history = (*write_guard).entry(id.to_string()).or_default();
+        precondition.check(history.current().map(|current| current.revision))?;
+        let revision = PolicyRevision::new(history.revisions.len() as u64 + 1, policy, change);
+        history.revisions.push(revision.clone());
+        history.deleted = false;
+        Ok(revision)
+    }
 
-    async fn get(&self, key: String) -> Result<Policy, Self::Error> {
+    async fn list_revisions(&self, id: &str) -> Result<Vec<PolicyRevision>, RepositoryError> {
         let read_guard = self.read().await;
-        match (*read_guard).get(&key) {
-            Some(entity) => Ok(entity.clone()),
-            None => bail!("Entity not found"),
+        match (*read_guard).get(id) {
+            Some(history) if !history.revisions.is_empty() => Ok(history.revisions.clone()),
+            _ => Err(RepositoryError::NotFound),
         }
     }
 
-    async fn upsert(&self, key: String, entity: Policy) -> Result<(), Self::Error> {
+    async fn get_revision(
+        &self,
+        id: &str,
+        revision: u64,
+    ) -> Result<PolicyRevision, RepositoryError> {
+        let read_guard = self.read().await;
+        (*read_guard)
+            .get(id)
+            .and_then(|history| history.revisions.iter().find(|r| r.revision == revision))
+            .cloned()
+            .ok_or(RepositoryError::NotFound)
+    }
+}
+
+#[async_trait]
+impl UpsertRepository<Role, String> for RwLock<Entries<String, Role>> {
+    type Error = RepositoryError;
+
+    async fn get_versioned(&self, key: String) -> Result<Versioned<Role>, Self::Error> {
+        let read_guard = self.read().await;
+        get_entry(&read_guard, &key)
+    }
+
+    async fn upsert_if(
+        &self,
+        key: String,
+        entity: Role,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
         let mut write_guard = self.write().await;
-        (*write_guard).insert(key, entity);
-        Ok(())
+        update_entry(&mut write_guard, key, precondition, |_| entity)
     }
 
-    async fn delete(&self, key: String) -> Result<(), Self::Error> {
+    async fn delete_if(&self, key: String, precondition: Precondition) -> Result<(), Self::Error> {
         let mut write_guard = self.write().await;
-        (*write_guard).remove(&key);
-        Ok(())
+        remove_entry(&mut write_guard, &key, precondition)
+    }
+
+    async fn list(&self, query: ListQuery<String>) -> Result<Page<String, Role>, Self::Error> {
+        let read_guard = self.read().await;
+        let entries = existing(&read_guard)
+            .filter(|(id, _)| matches_prefix(&query.filter, id))
+            .map(|(id, role)| (id.clone(), role.clone()));
+        Ok(page_of(entries, &query, |id| id.clone()))
     }
 }
 
 #[async_trait]
 impl UpsertRepository<PolicyAttachment, ExternalIdentity>
-    for RwLock<HashMap<ExternalIdentity, PolicyAttachment>>
+    for RwLock<Entries<ExternalIdentity, PolicyAttachment>>
 {
-    type Error = anyhow::Error;
+    type Error = RepositoryError;
 
-    async fn get(&self, key: ExternalIdentity) -> Result<PolicyAttachment, Self::Error> {
+    async fn get_versioned(
+        &self,
+        key: ExternalIdentity,
+    ) -> Result<Versioned<PolicyAttachment>, Self::Error> {
         let read_guard = self.read().await;
-        match (*read_guard).get(&key) {
-            Some(entity) => Ok(entity.clone()),
-            None => bail!("Entity not found"),
-        }
+        get_entry(&read_guard, &key)
     }
 
-    async fn upsert(
+    async fn upsert_if(
         &self,
         key: ExternalIdentity,
         entity: PolicyAttachment,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
+        let mut write_guard = self.write().await;
+        update_entry(&mut write_guard, key, precondition, |previous| {
+            let mut attachment = previous.unwrap_or_default();
+            attachment.merge(entity);
+            attachment
+        })
+    }
+
+    async fn delete_if(
+        &self,
+        key: ExternalIdentity,
+        precondition: Precondition,
     ) -> Result<(), Self::Error> {
         let mut write_guard = self.write().await;
-        match (*write_guard).get(&key) {
-            Some(entity) => {
-                let new_policies = entity.policies.union(&entity.policies).cloned().collect();
-                let new_entity = PolicyAttachment {
-                    policies: new_policies,
-                };
-                (*write_guard).insert(key, new_entity);
-            }
-            None => {
-                (*write_guard).insert(key, entity);
-            }
+        remove_entry(&mut write_guard, &key, precondition)
+    }
+
+    async fn list(
+        &self,
+        query: ListQuery<ExternalIdentity>,
+    ) -> Result<Page<ExternalIdentity, PolicyAttachment>, Self::Error> {
+        let read_guard = self.read().await;
+        let entries = existing(&read_guard)
+            .filter(|(key, attachment)| {
+                matches_identity_provider(&query.filter, &key.identity_provider)
+                    && matches_prefix(&query.filter, &key.user_id)
+                    && matches_attachment(&query.filter, attachment)
+            })
+            .map(|(key, attachment)| (key.clone(), attachment.clone()));
+        Ok(page_of(entries, &query, |key| {
+            (key.identity_provider.clone(), key.user_id.clone())
+        }))
+    }
+}
+
+#[async_trait]
+impl UpsertRepository<PolicyAttachment, ClaimAttachmentKey>
+    for RwLock<Entries<ClaimAttachmentKey, PolicyAttachment>>
+{
+    type Error = RepositoryError;
+
+    async fn get_versioned(
+        &self,
+        key: ClaimAttachmentKey,
+    ) -> Result<Versioned<PolicyAttachment>, Self::Error> {
+        let read_guard = self.read().await;
+        get_entry(&read_guard, &key)
+    }
+
+    async fn upsert_if(
+        &self,
+        key: ClaimAttachmentKey,
+        entity: PolicyAttachment,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
+        let mut write_guard = self.write().await;
+        update_entry(&mut write_guard, key, precondition, |previous| {
+            let mut attachment = previous.unwrap_or_default();
+            attachment.merge(entity);
+            attachment
+        })
+    }
+
+    async fn delete_if(
+        &self,
+        key: ClaimAttachmentKey,
+        precondition: Precondition,
+    ) -> Result<(), Self::Error> {
+        let mut write_guard = self.write().await;
+        remove_entry(&mut write_guard, &key, precondition)
+    }
+
+    async fn list(
+        &self,
+        query: ListQuery<ClaimAttachmentKey>,
+    ) -> Result<Page<ClaimAttachmentKey, PolicyAttachment>, Self::Error> {
+        let read_guard = self.read().await;
+        let entries = existing(&read_guard)
+            .filter(|(key, attachment)| {
+                matches_identity_provider(&query.filter, &key.identity_provider)
+                    && matches_attachment(&query.filter, attachment)
+            })
+            .map(|(key, attachment)| (key.clone(), attachment.clone()));
+        Ok(page_of(entries, &query, |key| {
+            (
+                key.identity_provider.clone(),
+                key.predicate.claim.clone(),
+                key.predicate.operator.as_str(),
+                key.predicate.value.clone(),
+            )
+        }))
+    }
+}
+
+#[async_trait]
+impl ClaimAttachmentStore for RwLock<Entries<ClaimAttachmentKey, PolicyAttachment>> {
+    async fn list_by_provider(
+        &self,
+        identity_provider: &str,
+    ) -> Result<Vec<ClaimAttachment>, RepositoryError> {
+        let identity_provider = identity_provider.to_lowercase();
+        let read_guard = self.read().await;
+        Ok(group_claim_attachments(
+            existing(&read_guard)
+                .filter(|(key, _)| key.identity_provider == identity_provider)
+                .map(|(key, attachment)| (key.predicate.clone(), attachment.clone())),
+        ))
+    }
+}
+
+#[async_trait]
+impl ExpiringRepository<Revocation> for RwLock<HashMap<RevocationTarget, Revocation>> {
+    type Error = RepositoryError;
+
+    async fn insert(&self, entity: Revocation) -> Result<(), Self::Error> {
+        let mut write_guard = self.write().await;
+        (*write_guard).retain(|_, revocation| revocation.is_active(entity.revoked_at));
+        (*write_guard).insert(entity.target.clone(), entity);
+        Ok(())
+    }
+
+    async fn list_active(&self, now: u64) -> Result<Vec<Revocation>, Self::Error> {
+        let read_guard = self.read().await;
+        Ok((*read_guard)
+            .values()
+            .filter(|revocation| revocation.is_active(now))
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl RefreshTokenStore for RwLock<HashMap<String, RefreshToken>> {
+    type Error = RepositoryError;
+
+    async fn insert(&self, token: RefreshToken) -> Result<(), Self::Error> {
+        let mut write_guard = self.write().await;
+        (*write_guard).retain(|_, existing| !existing.is_expired(token.issued_at));
+        (*write_guard).insert(token.token_hash.clone(), token);
+        Ok(())
+    }
+
+    async fn get_by_hash(&self, token_hash: &str) -> Result<RefreshToken, Self::Error> {
+        let read_guard = self.read().await;
+        (*read_guard)
+            .get(token_hash)
+            .cloned()
+            .ok_or(RepositoryError::NotFound)
+    }
+
+    async fn rotate(&self, token_hash: &str, rotated: RefreshToken) -> Result<(), Self::Error> {
+        let mut write_guard = self.write().await;
+        match (*write_guard).get_mut(token_hash) {
+            Some(token) if token.used => return Err(RepositoryError::PreconditionFailed),
+            Some(token) => token.used = true,
+            None => return Err(RepositoryError::NotFound),
         }
+        (*write_guard).retain(|_, existing| !existing.is_expired(rotated.issued_at));
+        (*write_guard).insert(rotated.token_hash.clone(), rotated);
         Ok(())
     }
 
-    async fn delete(&self, key: ExternalIdentity) -> Result<(), Self::Error> {
+    async fn delete_family(&self, family_id: &str) -> Result<(), Self::Error> {
         let mut write_guard = self.write().await;
-        (*write_guard).remove(&key);
+        (*write_guard).retain(|_, token| token.family_id != family_id);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::external::claim_attachment::{ClaimOperator, ClaimPredicate};
+    use crate::services::repositories::tests::{
+        assert_identity_keys_are_case_insensitive, assert_versions_increase_across_deletes,
+    };
+    use rstest::rstest;
+    use std::collections::HashSet;
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_identity_keys_are_case_insensitive() {
+        let repository: RwLock<Entries<(String, String), ExternalIdentity>> =
+            RwLock::new(HashMap::new());
+        assert_identity_keys_are_case_insensitive(&repository, "Provider").await;
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_versions_increase_across_deletes() {
+        let user = "recreated".to_string();
+
+        assert_versions_increase_across_deletes(
+            &RwLock::new(HashMap::new()),
+            ("provider".to_string(), user.clone()),
+            ExternalIdentity::new("provider".to_string(), user.clone()),
+        )
+        .await;
+        assert_versions_increase_across_deletes(
+            &RwLock::new(HashMap::new()),
+            user.clone(),
+            Role {
+                policies: HashSet::from(["reader".to_string()]),
+            },
+        )
+        .await;
+        assert_versions_increase_across_deletes(
+            &RwLock::new(HashMap::new()),
+            ExternalIdentity::new("provider".to_string(), user.clone()),
+            PolicyAttachment::single("reader".to_string()),
+        )
+        .await;
+        assert_versions_increase_across_deletes(
+            &RwLock::new(HashMap::new()),
+            ClaimAttachmentKey::new(
+                "provider".to_string(),
+                ClaimPredicate {
+                    claim: "groups".to_string(),
+                    operator: ClaimOperator::Contains,
+                    value: user,
+                },
+            ),
+            PolicyAttachment::single("reader".to_string()),
+        )
+        .await;
+    }
+}
@@ -1 +1,453 @@
 pub mod in_memory;
+pub mod postgres;
+pub mod sqlite;
+
+use crate::models::external::claim_attachment::{ClaimAttachment, ClaimOperator, ClaimPredicate};
+use crate::models::external::identity::{Policy, PolicyAttachment};
+use crate::models::external::policy_revision::PolicyRevision;
+use crate::models::internal::revocation::{Revocation, RevocationTarget};
+use crate::models::internal::settings::RepositorySettings;
+use crate::services::base::claim_attachment_store::ClaimAttachmentRepository;
+use crate::services::base::expiring_repository::RevocationRepository;
+use crate::services::base::policy_revision_store::PolicyRepository;
+use crate::services::base::refresh_token_store::RefreshTokenRepository;
+use crate::services::base::upsert_repository::{
+    IdentityRepository, PolicyAttachmentRepository, RepositoryError, RoleRepository,
+};
+use crate::services::external_identity_validator::DynamicClaimsCollection;
+use crate::services::repositories::postgres::PostgresRepository;
+use crate::services::repositories::sqlite::SqliteRepository;
+use log::warn;
+use sqlx::error::ErrorKind;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Repositories used by the application services.
+pub struct Repositories {
+    pub identities: Arc<IdentityRepository>,
+    pub policies: Arc<PolicyRepository>,
+    pub roles: Arc<RoleRepository>,
+    pub policy_attachments: Arc<PolicyAttachmentRepository>,
+    pub claim_attachments: Arc<ClaimAttachmentRepository>,
+    pub revocations: Arc<RevocationRepository>,
+    pub refresh_tokens: Arc<RefreshTokenRepository>,
+}
+
+/// Creates the repositories for the configured storage backend.
+pub async fn new(settings: &RepositorySettings) -> Result<Repositories, anyhow::Error> {
+    match settings {
+        RepositorySettings::InMemory => Ok(Repositories {
+            identities: Arc::new(RwLock::new(HashMap::new())),
+            policies: Arc::new(RwLock::new(HashMap::new())),
+            roles: Arc::new(RwLock::new(HashMap::new())),
+            policy_attachments: Arc::new(RwLock::new(HashMap::new())),
+            claim_attachments: Arc::new(RwLock::new(HashMap::new())),
+            revocations: Arc::new(RwLock::new(HashMap::new())),
+            refresh_tokens: Arc::new(RwLock::new(HashMap::new())),
+        }),
+        RepositorySettings::Sqlite { url } => {
+            let repository = SqliteRepository::connect(url).await?;
+            Ok(Repositories {
+                identities: Arc::new(repository.clone()),
+                policies: Arc::new(repository.clone()),
+                roles: Arc::new(repository.clone()),
+                policy_attachments: Arc::new(repository.clone()),
+                claim_attachments: Arc::new(repository.clone()),
+                revocations: Arc::new(repository.clone()),
+                refresh_tokens: Arc::new(repository),
+            })
+        }
+        RepositorySettings::Postgres {
+            url,
+            max_connections,
+        } => {
+            let repository = PostgresRepository::connect(url, *max_connections).await?;
+            Ok(Repositories {
+                identities: Arc::new(repository.clone()),
+                policies: Arc::new(repository.clone()),
+                roles: Arc::new(repository.clone()),
+                policy_attachments: Arc::new(repository.clone()),
+                claim_attachments: Arc::new(repository.clone()),
+                revocations: Arc::new(repository.clone()),
+                refresh_tokens: Arc::new(repository),
+            })
+        }
+    }
+}
+
+/// Columns that identify the target of a revocation in the database backends:
+/// the target type, the identity provider (empty unless the target is an identity) and the target id.
+fn revocation_key(target: &RevocationTarget) -> (&'static str, &str, &str) {
+    match target {
+        RevocationTarget::Token { token_id } => ("token", "", token_id),
+        RevocationTarget::Identity {
+            identity_provider,
+            user_id,
+        } => ("identity", identity_provider, user_id),
+        RevocationTarget::Policy { policy_id } => ("policy", "", policy_id),
+    }
+}
+
+/// Policy ids are stored as a space-separated list, like the OAuth scope they are requested with.
+fn encode_policies<'a>(policies: impl IntoIterator<Item = &'a String>) -> String {
+    let sorted: BTreeSet<&String> = policies.into_iter().collect();
+    sorted.into_iter().cloned().collect::<Vec<_>>().join(" ")
+}
+
+fn decode_policies(value: &str) -> BTreeSet<String> {
+    value.split_whitespace().map(|p| p.to_string()).collect()
+}
+
+/// Groups the attached policies and roles by predicate, ordered by claim, operator and value.
+fn group_claim_attachments(
+    entries: impl IntoIterator<Item = (ClaimPredicate, PolicyAttachment)>,
+) -> Vec<ClaimAttachment> {
+    let mut grouped: HashMap<ClaimPredicate, PolicyAttachment> = HashMap::new();
+    for (predicate, attachment) in entries {
+        grouped.entry(predicate).or_default().merge(attachment);
+    }
+    let mut attachments: Vec<ClaimAttachment> = grouped
+        .into_iter()
+        .map(|(predicate, attachment)| ClaimAttachment {
+            predicate,
+            attachment,
+        })
+        .collect();
+    attachments.sort_by(|a, b| {
+        (
+            &a.predicate.claim,
+            a.predicate.operator.as_str(),
+            &a.predicate.value,
+        )
+            .cmp(&(
+                &b.predicate.claim,
+                b.predicate.operator.as_str(),
+                &b.predicate.value,
+            ))
+    });
+    attachments
+}
+
+/// Restores an attachment from the `(kind, id, revision)` rows of the attached policies and roles,
+/// where the revision a policy is pinned to is `NULL` for the unpinned policies and the roles.
+fn attachment_from_rows(
+    rows: impl IntoIterator<Item = (String, String, Option<i64>)>,
+) -> PolicyAttachment {
+    let mut attachment = PolicyAttachment::default();
+    for (kind, id, revision) in rows {
+        match kind.as_str() {
+            "role" => {
+                attachment.roles.insert(id);
+            }
+            _ => {
+                if let Some(revision) = revision {
+                    attachment.revisions.insert(id.clone(), revision as u64);
+                }
+                attachment.policies.insert(id);
+            }
+        };
+    }
+    attachment
+}
+
+/// Groups the `(kind, id, revision)` rows of listed attachments by key, see `attachment_from_rows`.
+/// The rows must be ordered by key, and the order is kept.
+fn group_attachment_rows<K: PartialEq>(
+    rows: impl IntoIterator<Item = (K, (String, String, Option<i64>))>,
+) -> Vec<(K, PolicyAttachment)> {
+    let mut grouped: Vec<(K, PolicyAttachment)> = Vec::new();
+    for (key, row) in rows {
+        let attachment = attachment_from_rows([row]);
+        match grouped.last_mut() {
+            Some((last, existing)) if *last == key => existing.merge(attachment),
+            _ => grouped.push((key, attachment)),
+        }
+    }
+    grouped
+}
+
+/// Tables of the entities whose versions are stored separately in the database backends.
+/// Versions are kept when the entity is deleted, so a recreated entity continues from the version of the deleted one.
+struct VersionedTable {
+    /// The table of the versions, by key
+    versions: &'static str,
+
+    /// The tables of the entity rows, the entity exists if one of them has a row with its key
+    entities: &'static [&'static str],
+
+    /// The key columns, named the same in all tables
+    key: &'static [&'static str],
+}
+
+const IDENTITIES: VersionedTable = VersionedTable {
+    versions: "identity_versions",
+    entities: &["identities"],
+    key: &["identity_provider", "user_id"],
+};
+
+const ROLES: VersionedTable = VersionedTable {
+    versions: "role_versions",
+    entities: &["roles"],
+    key: &["id"],
+};
+
+const ATTACHMENTS: VersionedTable = VersionedTable {
+    versions: "attachment_versions",
+    entities: &["policy_attachments", "role_attachments"],
+    key: &["identity_provider", "user_id"],
+};
+
+const CLAIM_ATTACHMENTS: VersionedTable = VersionedTable {
+    versions: "claim_attachment_versions",
+    entities: &["claim_attachments", "claim_role_attachments"],
+    key: &["identity_provider", "claim", "operator", "value"],
+};
+
+impl VersionedTable {
+    /// Matches the key columns with the numbered parameters, prefixed with `placeholder` (`?` or `$`)
+    fn key_condition(&self, placeholder: char) -> String {
+        self.key
+            .iter()
+            .enumerate()
+            .map(|(i, column)| format!("{} = {}{}", column, placeholder, i + 1))
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    }
+
+    /// Increments the version of the key, starting at 1, and returns the new version
+    fn increment_version_query(&self, placeholder: char) -> String {
+        let columns = self.key.join(", ");
+        let parameters: Vec<String> = (1..=self.key.len())
+            .map(|i| format!("{}{}", placeholder, i))
+            .collect();
+        format!(
+            "INSERT INTO {versions} ({columns}, version) VALUES ({parameters}, 1) ON CONFLICT ({columns}) DO UPDATE SET version = {versions}.version + 1 RETURNING version",
+            versions = self.versions,
+            columns = columns,
+            parameters = parameters.join(", "),
+        )
+    }
+
+    /// Checks whether the entity with the key exists
+    fn exists_query(&self, placeholder: char) -> String {
+        let condition = self.key_condition(placeholder);
+        let exists: Vec<String> = self
+            .entities
+            .iter()
+            .map(|table| format!("EXISTS (SELECT 1 FROM {} WHERE {})", table, condition))
+            .collect();
+        format!("SELECT {}", exists.join(" OR "))
+    }
+
+    /// Deletes the rows of the entity with the key, keeping its version
+    fn delete_queries(&self, placeholder: char) -> Vec<String> {
+        let condition = self.key_condition(placeholder);
+        self.entities
+            .iter()
+            .map(|table| format!("DELETE FROM {} WHERE {}", table, condition))
+            .collect()
+    }
+}
+
+/// The version an entity had before the change that stored `version`, `None` if it did not exist.
+fn previous_version(version: i64, existed: bool) -> Option<u64> {
+    existed.then(|| version as u64 - 1)
+}
+
+/// Restores a policy revision from the columns written by the database backends.
+fn policy_revision_from_row(
+    revision: i64,
+    content: String,
+    template: bool,
+    author: Option<String>,
+    reason: Option<String>,
+    created_at: i64,
+) -> PolicyRevision {
+    PolicyRevision {
+        revision: revision as u64,
+        policy: Policy { content, template },
+        author,
+        reason,
+        created_at: created_at as u64,
+    }
+}
+
+/// Restores a claim predicate from the columns written by the database backends.
+fn claim_predicate_from_row(
+    claim: String,
+    operator: String,
+    value: String,
+) -> Result<ClaimPredicate, RepositoryError> {
+    let operator = ClaimOperator::parse(&operator).ok_or_else(|| {
+        RepositoryError::Backend(anyhow::anyhow!("Unknown claim operator '{}'", operator))
+    })?;
+    Ok(ClaimPredicate {
+        claim,
+        operator,
+        value,
+    })
+}
+
+/// External token claims are stored as a JSON object.
+fn encode_claims(claims: &DynamicClaimsCollection) -> Result<String, RepositoryError> {
+    serde_json::to_string(claims).map_err(|e| RepositoryError::Backend(e.into()))
+}
+
+fn decode_claims(value: Option<String>) -> Result<DynamicClaimsCollection, RepositoryError> {
+    match value {
+        Some(value) => serde_json::from_str(&value).map_err(|e| RepositoryError::Backend(e.into())),
+        None => Ok(DynamicClaimsCollection::new()),
+    }
+}
+
+/// Restores a revocation from the columns written by the database backends.
+fn revocation_from_row(
+    target_type: String,
+    identity_provider: String,
+    target_id: String,
+    revoked_at: i64,
+    expires_at: i64,
+) -> Result<Revocation, RepositoryError> {
+    let target = match target_type.as_str() {
+        "token" => RevocationTarget::Token {
+            token_id: target_id,
+        },
+        "identity" => RevocationTarget::Identity {
+            identity_provider,
+            user_id: target_id,
+        },
+        "policy" => RevocationTarget::Policy {
+            policy_id: target_id,
+        },
+        other => {
+            return Err(RepositoryError::Backend(anyhow::anyhow!(
+                "Unknown revocation target type '{}'",
+                other
+            )))
+        }
+    };
+    Ok(Revocation {
+        target,
+        revoked_at: revoked_at as u64,
+        expires_at: expires_at as u64,
+    })
+}
+
+impl From<sqlx::Error> for RepositoryError {
+    fn from(value: sqlx::Error) -> Self {
+        match &value {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound,
+            // The database messages name tables and constraints, so they are logged instead of returned to clients
+            sqlx::Error::Database(e) => match e.kind() {
+                ErrorKind::UniqueViolation => {
+                    warn!("Unique constraint violated: {}", e.message());
+                    RepositoryError::Conflict("Entity already exists".to_string())
+                }
+                ErrorKind::ForeignKeyViolation
+                | ErrorKind::NotNullViolation
+                | ErrorKind::CheckViolation => {
+                    warn!("Constraint violated: {}", e.message());
+                    RepositoryError::Invalid("Invalid value".to_string())
+                }
+                _ => RepositoryError::Backend(value.into()),
+            },
+            _ => RepositoryError::Backend(value.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::models::external::identity::ExternalIdentity;
+    use crate::services::base::upsert_repository::{
+        ListFilter, ListQuery, Page, Precondition, RepositoryError, UpsertRepository, VersionMatch,
+    };
+
+    /// Deletes and recreates the entity, and checks that the version of the deleted entity no longer matches
+    pub(crate) async fn assert_versions_increase_across_deletes<Entity, Key, R>(
+        repository: &R,
+        key: Key,
+        entity: Entity,
+    ) where
+        Entity: Clone + Send + 'static,
+        Key: Clone + Send + 'static,
+        R: UpsertRepository<Entity, Key, Error = RepositoryError>,
+    {
+        let version = repository
+            .upsert_if(key.clone(), entity.clone(), Precondition::default())
+            .await
+            .unwrap();
+        repository.delete(key.clone()).await.unwrap();
+        let recreated = repository
+            .upsert_if(key.clone(), entity.clone(), Precondition::default())
+            .await
+            .unwrap();
+        assert!(recreated > version + 1);
+
+        let stale = Precondition {
+            if_match: Some(VersionMatch::Versions(vec![version])),
+            if_none_match: None,
+        };
+        assert!(matches!(
+            repository
+                .upsert_if(key.clone(), entity, stale.clone())
+                .await,
+            Err(RepositoryError::PreconditionFailed)
+        ));
+        assert!(matches!(
+            repository.delete_if(key, stale).await,
+            Err(RepositoryError::PreconditionFailed)
+        ));
+    }
+
+    /// Stores identities with a mixed case key, and checks that they are found and listed by the lowercase key.
+    /// The identity provider must be in mixed case and not used by other identities of the repository.
+    pub(crate) async fn assert_identity_keys_are_case_insensitive<R>(
+        repository: &R,
+        identity_provider: &str,
+    ) where
+        R: UpsertRepository<ExternalIdentity, (String, String), Error = RepositoryError>,
+    {
+        let key = (
+            identity_provider.to_string(),
+            "User@Example.com".to_string(),
+        );
+
+        repository
+            .upsert(key.clone(), ExternalIdentity::from(key.clone()))
+            .await
+            .unwrap();
+        repository
+            .upsert(
+                (key.0.clone(), "zed".to_string()),
+                ExternalIdentity::new(key.0.clone(), "zed".to_string()),
+            )
+            .await
+            .unwrap();
+        let identity = repository
+            .get((
+                identity_provider.to_lowercase(),
+                "user@example.com".to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(identity, ExternalIdentity::from(key.clone()));
+
+        let query = ListQuery {
+            after: Some(key),
+            limit: 10,
+            filter: ListFilter {
+                identity_provider: Some(identity_provider.to_lowercase()),
+                ..ListFilter::default()
+            },
+        };
+        let page: Page<(String, String), ExternalIdentity> = repository.list(query).await.unwrap();
+        let users: Vec<&str> = page
+            .items
+            .iter()
+            .map(|(_, identity)| identity.user_id.as_str())
+            .collect();
+        assert_eq!(users, vec!["zed"]);
+    }
+}
@@ -0,0 +1,1553 @@
+use crate::models::external::claim_attachment::{ClaimAttachment, ClaimAttachmentKey};
+use crate::models::external::identity::{ExternalIdentity, Policy, PolicyAttachment, Role};
+use crate::models::external::policy_revision::{PolicyChange, PolicyRevision};
+use crate::models::internal::refresh_token::RefreshToken;
+use crate::models::internal::revocation::Revocation;
+use crate::services::base::claim_attachment_store::ClaimAttachmentStore;
+use crate::services::base::expiring_repository::ExpiringRepository;
+use crate::services::base::policy_revision_store::PolicyRevisionStore;
+use crate::services::base::refresh_token_store::RefreshTokenStore;
+use crate::services::base::upsert_repository::{
+    ListQuery, Page, Precondition, RepositoryError, UpsertRepository, Versioned,
+};
+use crate::services::repositories::{
+    attachment_from_rows, claim_predicate_from_row, decode_claims, decode_policies, encode_claims,
+    encode_policies, group_attachment_rows, group_claim_attachments, policy_revision_from_row,
+    previous_version, revocation_from_row, revocation_key, VersionedTable, ATTACHMENTS,
+    CLAIM_ATTACHMENTS, IDENTITIES, ROLES,
+};
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::{Row, Sqlite, Transaction};
+use std::str::FromStr;
+
+/// Repository that stores policies, identities and policy attachments in a SQLite database.
+#[derive(Clone)]
+pub struct SqliteRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRepository {
+    /// Connects to the database at the given url, creating it if necessary, and applies the schema migrations.
+    pub async fn connect(url: &str) -> Result<Self, anyhow::Error> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+        Ok(SqliteRepository { pool })
+    }
+}
+
+impl SqliteRepository {
+    /// Increments the version of the entity, which makes concurrent changes of the entity wait for the transaction,
+    /// and returns the new version with the version before the change, `None` if the entity did not exist.
+    async fn increment_version(
+        transaction: &mut Transaction<'_, Sqlite>,
+        table: &VersionedTable,
+        key: &[&str],
+    ) -> Result<(u64, Option<u64>), RepositoryError> {
+        let increment = table.increment_version_query('?');
+        let mut query = sqlx::query(&increment);
+        for value in key {
+            query = query.bind(*value);
+        }
+        let version: i64 = query.fetch_one(&mut **transaction).await?.get(0);
+        let exists = table.exists_query('?');
+        let mut query = sqlx::query(&exists);
+        for value in key {
+            query = query.bind(*value);
+        }
+        let existed: bool = query.fetch_one(&mut **transaction).await?.get(0);
+        Ok((version as u64, previous_version(version, existed)))
+    }
+
+    /// Deletes the entity if its version satisfies the precondition. The version is kept for a recreated entity.
+    async fn delete_versioned(
+        &self,
+        table: &VersionedTable,
+        key: &[&str],
+        precondition: Precondition,
+    ) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        let (_, current) = Self::increment_version(&mut transaction, table, key).await?;
+        precondition.check(current)?;
+        if current.is_none() {
+            return Err(RepositoryError::NotFound);
+        }
+        for delete in table.delete_queries('?') {
+            let mut query = sqlx::query(&delete);
+            for value in key {
+                query = query.bind(*value);
+            }
+            query.execute(&mut *transaction).await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UpsertRepository<ExternalIdentity, (String, String)> for SqliteRepository {
+    type Error = RepositoryError;
+
+    async fn get_versioned(
+        &self,
+        key: (String, String),
+    ) -> Result<Versioned<ExternalIdentity>, Self::Error> {
+        let key = ExternalIdentity::from(key);
+        let row = sqlx::query(
+            "SELECT i.identity_provider, i.user_id, v.version FROM identities i JOIN identity_versions v ON v.identity_provider = i.identity_provider AND v.user_id = i.user_id WHERE i.identity_provider = ? AND i.user_id = ?",
+        )
+        .bind(&key.identity_provider)
+        .bind(&key.user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            Some(row) => Ok(Versioned::new(
+                ExternalIdentity::new(row.get(0), row.get(1)),
+                row.get::<i64, _>(2) as u64,
+            )),
+            None => Err(RepositoryError::NotFound),
+        }
+    }
+
+    async fn upsert_if(
+        &self,
+        _key: (String, String),
+        entity: ExternalIdentity,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let (version, current) = Self::increment_version(
+            &mut transaction,
+            &IDENTITIES,
+            &[&entity.identity_provider, &entity.user_id],
+        )
+        .await?;
+        precondition.check(current)?;
+        sqlx::query(
+            "INSERT INTO identities (identity_provider, user_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+        )
+        .bind(&entity.identity_provider)
+        .bind(&entity.user_id)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(version)
+    }
+
+    async fn delete_if(
+        &self,
+        key: (String, String),
+        precondition: Precondition,
+    ) -> Result<(), Self::Error> {
+        let key = ExternalIdentity::from(key);
+        self.delete_versioned(
+            &IDENTITIES,
+            &[&key.identity_provider, &key.user_id],
+            precondition,
+        )
+        .await
+    }
+
+    async fn list(
+        &self,
+        query: ListQuery<(String, String)>,
+    ) -> Result<Page<(String, String), ExternalIdentity>, Self::Error> {
+        let after = query.after.map(ExternalIdentity::from);
+        let rows = sqlx::query(
+            "SELECT identity_provider, user_id FROM identities WHERE (?1 IS NULL OR (identity_provider, user_id) > (?1, ?2)) AND (?3 IS NULL OR identity_provider = ?3) AND (?4 IS NULL OR substr(user_id, 1, length(?4)) = ?4) ORDER BY identity_provider, user_id LIMIT ?5",
+        )
+        .bind(after.as_ref().map(|key| &key.identity_provider))
+        .bind(after.as_ref().map(|key| &key.user_id))
+        .bind(&query.filter.identity_provider)
+        .bind(&query.filter.prefix)
+        .bind((query.limit + 1) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let items = rows
+            .into_iter()
+            .map(|row| {
+                let key: (String, String) = (row.get(0), row.get(1));
+                (key.clone(), ExternalIdentity::from(key))
+            })
+            .collect();
+        Ok(Page::new(items, query.limit))
+    }
+}
+
+#[async_trait]
+impl UpsertRepository<Policy, String> for SqliteRepository {
+    type Error = RepositoryError;
+
+    async fn get_versioned(&self, key: String) -> Result<Versioned<Policy>, Self::Error> {
+        let row = sqlx::query("SELECT content, template, version FROM policies WHERE id = ?")
+            .bind(&key)
+            .fetch_optional(&self.pool)
+            .await?;
+        match row {
+            Some(row) => Ok(Versioned::new(
+                Policy {
+                    content: row.get(0),
+                    template: row.get(1),
+                },
+                row.get::<i64, _>(2) as u64,
+            )),
+            None => Err(RepositoryError::NotFound),
+        }
+    }
+
+    async fn upsert_if(
+        &self,
+        key: String,
+        entity: Policy,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
+        let revision = self
+            .upsert_revision(&key, entity, PolicyChange::default(), precondition)
+            .await?;
+        Ok(revision.revision)
+    }
+
+    async fn delete_if(&self, key: String, precondition: Precondition) -> Result<(), Self::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let deleted = sqlx::query("DELETE FROM policies WHERE id = ? RETURNING version")
+            .bind(&key)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        let current = deleted.map(|row| row.get::<i64, _>(0) as u64);
+        precondition.check(current)?;
+        transaction.commit().await?;
+        match current {
+            Some(_) => Ok(()),
+            None => Err(RepositoryError::NotFound),
+        }
+    }
+
+    async fn list(&self, query: ListQuery<String>) -> Result<Page<String, Policy>, Self::Error> {
+        let rows = sqlx::query(
+            "SELECT id, content, template FROM policies WHERE (?1 IS NULL OR id > ?1) AND (?2 IS NULL OR substr(id, 1, length(?2)) = ?2) ORDER BY id LIMIT ?3",
+        )
+        .bind(&query.after)
+        .bind(&query.filter.prefix)
+        .bind((query.limit + 1) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let items = rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get(0),
+                    Policy {
+                        content: row.get(1),
+                        template: row.get(2),
+                    },
+                )
+            })
+            .collect();
+        Ok(Page::new(items, query.limit))
+    }
+}
+
+#[async_trait]
+impl PolicyRevisionStore for SqliteRepository {
+    async fn upsert_revision(
+        &self,
+        id: &str,
+        policy: Policy,
+        change: PolicyChange,
+        precondition: Precondition,
+    ) -> Result<PolicyRevision, RepositoryError> {
+        // The revision is numbered and made current in a single transaction, that starts with a write,
+        // so concurrent upserts wait for the write lock instead of failing on a stale read.
+        let mut transaction = self.pool.begin().await?;
+        let current =
+            sqlx::query("UPDATE policies SET version = version WHERE id = ? RETURNING version")
+                .bind(id)
+                .fetch_optional(&mut *transaction)
+                .await?
+                .map(|row| row.get::<i64, _>(0) as u64);
+        precondition.check(current)?;
+        let next: i64 = sqlx::query(
+            "SELECT COALESCE(MAX(revision), 0) + 1 FROM policy_revisions WHERE policy_id = ?",
+        )
+        .bind(id)
+        .fetch_one(&mut *transaction)
+        .await?
+        .get(0);
+        let revision = PolicyRevision::new(next as u64, policy, change);
+        sqlx::query(
+            "INSERT INTO policy_revisions (policy_id, revision, content, template, author, reason, created_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(next)
+        .bind(&revision.policy.content)
+        .bind(revision.policy.template)
+        .bind(&revision.author)
+        .bind(&revision.reason)
+        .bind(revision.created_at as i64)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(
+            "INSERT INTO policies (id, content, template, version) VALUES (?, ?, ?, ?) ON CONFLICT (id) DO UPDATE SET content = excluded.content, template = excluded.template, version = excluded.version",
+        )
+        .bind(id)
+        .bind(&revision.policy.content)
+        .bind(revision.policy.template)
+        .bind(next)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(revision)
+    }
+
+    async fn list_revisions(&self, id: &str) -> Result<Vec<PolicyRevision>, RepositoryError> {
+        let rows = sqlx::query(
+            "SELECT revision, content, template, author, reason, created_at FROM policy_revisions WHERE policy_id = ? ORDER BY revision",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+        if rows.is_empty() {
+            return Err(RepositoryError::NotFound);
+        }
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                policy_revision_from_row(
+                    row.get(0),
+                    row.get(1),
+                    row.get(2),
+                    row.get(3),
+                    row.get(4),
+                    row.get(5),
+                )
+            })
+            .collect())
+    }
+
+    async fn get_revision(
+        &self,
+        id: &str,
+        revision: u64,
+    ) -> Result<PolicyRevision, RepositoryError> {
+        let row = sqlx::query(
+            "SELECT revision, content, template, author, reason, created_at FROM policy_revisions WHERE policy_id = ? AND revision = ?",
+        )
+        .bind(id)
+        .bind(revision as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            Some(row) => Ok(policy_revision_from_row(
+                row.get(0),
+                row.get(1),
+                row.get(2),
+                row.get(3),
+                row.get(4),
+                row.get(5),
+            )),
+            None => Err(RepositoryError::NotFound),
+        }
+    }
+}
+
+#[async_trait]
+impl UpsertRepository<Role, String> for SqliteRepository {
+    type Error = RepositoryError;
+
+    async fn get_versioned(&self, key: String) -> Result<Versioned<Role>, Self::Error> {
+        let row = sqlx::query(
+            "SELECT r.policies, v.version FROM roles r JOIN role_versions v ON v.id = r.id WHERE r.id = ?",
+        )
+        .bind(&key)
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            Some(row) => Ok(Versioned::new(
+                Role {
+                    policies: decode_policies(row.get(0)).into_iter().collect(),
+                },
+                row.get::<i64, _>(1) as u64,
+            )),
+            None => Err(RepositoryError::NotFound),
+        }
+    }
+
+    async fn upsert_if(
+        &self,
+        key: String,
+        entity: Role,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let (version, current) = Self::increment_version(&mut transaction, &ROLES, &[&key]).await?;
+        precondition.check(current)?;
+        sqlx::query(
+            "INSERT INTO roles (id, policies) VALUES (?, ?) ON CONFLICT (id) DO UPDATE SET policies = excluded.policies",
+        )
+        .bind(&key)
+        .bind(encode_policies(&entity.policies))
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(version)
+    }
+
+    async fn delete_if(&self, key: String, precondition: Precondition) -> Result<(), Self::Error> {
+        self.delete_versioned(&ROLES, &[&key], precondition).await
+    }
+
+    async fn list(&self, query: ListQuery<String>) -> Result<Page<String, Role>, Self::Error> {
+        let rows = sqlx::query(
+            "SELECT id, policies FROM roles WHERE (?1 IS NULL OR id > ?1) AND (?2 IS NULL OR substr(id, 1, length(?2)) = ?2) ORDER BY id LIMIT ?3",
+        )
+        .bind(&query.after)
+        .bind(&query.filter.prefix)
+        .bind((query.limit + 1) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let items = rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get(0),
+                    Role {
+                        policies: decode_policies(row.get(1)).into_iter().collect(),
+                    },
+                )
+            })
+            .collect();
+        Ok(Page::new(items, query.limit))
+    }
+}
+
+#[async_trait]
+impl UpsertRepository<PolicyAttachment, ExternalIdentity> for SqliteRepository {
+    type Error = RepositoryError;
+
+    async fn get_versioned(
+        &self,
+        key: ExternalIdentity,
+    ) -> Result<Versioned<PolicyAttachment>, Self::Error> {
+        // The version is read first, so a concurrent change makes it stale rather than newer than the attachment
+        let version = sqlx::query(
+            "SELECT version FROM attachment_versions WHERE identity_provider = ? AND user_id = ?",
+        )
+        .bind(&key.identity_provider)
+        .bind(&key.user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let rows = sqlx::query(
+            "SELECT 'policy', policy_id, revision FROM policy_attachments WHERE identity_provider = ?1 AND user_id = ?2 UNION ALL SELECT 'role', role_id, NULL FROM role_attachments WHERE identity_provider = ?1 AND user_id = ?2",
+        )
+        .bind(&key.identity_provider)
+        .bind(&key.user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        match (rows.is_empty(), version) {
+            (false, Some(version)) => Ok(Versioned::new(
+                attachment_from_rows(rows.iter().map(|row| (row.get(0), row.get(1), row.get(2)))),
+                version.get::<i64, _>(0) as u64,
+            )),
+            _ => Err(RepositoryError::NotFound),
+        }
+    }
+
+    async fn upsert_if(
+        &self,
+        key: ExternalIdentity,
+        entity: PolicyAttachment,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
+        // Attachments are merged with the existing ones, so the policies and roles are inserted in a single transaction
+        let mut transaction = self.pool.begin().await?;
+        let (version, current) = Self::increment_version(
+            &mut transaction,
+            &ATTACHMENTS,
+            &[&key.identity_provider, &key.user_id],
+        )
+        .await?;
+        precondition.check(current)?;
+        for policy_id in entity.policies {
+            sqlx::query(
+                "INSERT INTO policy_attachments (identity_provider, user_id, policy_id, revision) VALUES (?, ?, ?, ?) ON CONFLICT (identity_provider, user_id, policy_id) DO UPDATE SET revision = excluded.revision",
+            )
+            .bind(&key.identity_provider)
+            .bind(&key.user_id)
+            .bind(&policy_id)
+            .bind(entity.revisions.get(&policy_id).map(|r| *r as i64))
+            .execute(&mut *transaction)
+            .await?;
+        }
+        for role_id in entity.roles {
+            sqlx::query(
+                "INSERT INTO role_attachments (identity_provider, user_id, role_id) VALUES (?, ?, ?) ON CONFLICT DO NOTHING",
+            )
+            .bind(&key.identity_provider)
+            .bind(&key.user_id)
+            .bind(&role_id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(version)
+    }
+
+    async fn delete_if(
+        &self,
+        key: ExternalIdentity,
+        precondition: Precondition,
+    ) -> Result<(), Self::Error> {
+        self.delete_versioned(
+            &ATTACHMENTS,
+            &[&key.identity_provider, &key.user_id],
+            precondition,
+        )
+        .await
+    }
+
+    async fn list(
+        &self,
+        query: ListQuery<ExternalIdentity>,
+    ) -> Result<Page<ExternalIdentity, PolicyAttachment>, Self::Error> {
+        // The page is selected from the versions of the existing attachments, and then joined with the attachment rows
+        let rows = sqlx::query(
+            r#"
+            WITH page AS (
+                SELECT identity_provider, user_id
+                FROM attachment_versions v
+                WHERE (?1 IS NULL OR (identity_provider, user_id) > (?1, ?2))
+                  AND (?3 IS NULL OR identity_provider = ?3)
+                  AND (?4 IS NULL OR substr(user_id, 1, length(?4)) = ?4)
+                  AND (?5 IS NULL OR EXISTS (
+                      SELECT 1 FROM policy_attachments a
+                      WHERE a.identity_provider = v.identity_provider AND a.user_id = v.user_id AND a.policy_id = ?5))
+                  AND (?6 IS NULL OR EXISTS (
+                      SELECT 1 FROM role_attachments a
+                      WHERE a.identity_provider = v.identity_provider AND a.user_id = v.user_id AND a.role_id = ?6))
+                  AND (EXISTS (
+                           SELECT 1 FROM policy_attachments a
+                           WHERE a.identity_provider = v.identity_provider AND a.user_id = v.user_id)
+                       OR EXISTS (
+                           SELECT 1 FROM role_attachments a
+                           WHERE a.identity_provider = v.identity_provider AND a.user_id = v.user_id))
+                ORDER BY identity_provider, user_id
+                LIMIT ?7
+            )
+            SELECT page.identity_provider, page.user_id, 'policy', a.policy_id, a.revision
+            FROM page
+            JOIN policy_attachments a ON a.identity_provider = page.identity_provider AND a.user_id = page.user_id
+            UNION ALL
+            SELECT page.identity_provider, page.user_id, 'role', a.role_id, NULL
+            FROM page
+            JOIN role_attachments a ON a.identity_provider = page.identity_provider AND a.user_id = page.user_id
+            ORDER BY 1, 2
+            "#,
+        )
+        .bind(query.after.as_ref().map(|key| &key.identity_provider))
+        .bind(query.after.as_ref().map(|key| &key.user_id))
+        .bind(&query.filter.identity_provider)
+        .bind(&query.filter.prefix)
+        .bind(&query.filter.policy)
+        .bind(&query.filter.role)
+        .bind((query.limit + 1) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let items = group_attachment_rows(rows.iter().map(|row| {
+            (
+                ExternalIdentity::new(row.get(0), row.get(1)),
+                (row.get(2), row.get(3), row.get(4)),
+            )
+        }));
+        Ok(Page::new(items, query.limit))
+    }
+}
+
+#[async_trait]
+impl UpsertRepository<PolicyAttachment, ClaimAttachmentKey> for SqliteRepository {
+    type Error = RepositoryError;
+
+    async fn get_versioned(
+        &self,
+        key: ClaimAttachmentKey,
+    ) -> Result<Versioned<PolicyAttachment>, Self::Error> {
+        // The version is read first, so a concurrent change makes it stale rather than newer than the attachment
+        let version = sqlx::query(
+            "SELECT version FROM claim_attachment_versions WHERE identity_provider = ? AND claim = ? AND operator = ? AND value = ?",
+        )
+        .bind(&key.identity_provider)
+        .bind(&key.predicate.claim)
+        .bind(key.predicate.operator.as_str())
+        .bind(&key.predicate.value)
+        .fetch_optional(&self.pool)
+        .await?;
+        let rows = sqlx::query(
+            "SELECT 'policy', policy_id, revision FROM claim_attachments WHERE identity_provider = ?1 AND claim = ?2 AND operator = ?3 AND value = ?4 UNION ALL SELECT 'role', role_id, NULL FROM claim_role_attachments WHERE identity_provider = ?1 AND claim = ?2 AND operator = ?3 AND value = ?4",
+        )
+        .bind(&key.identity_provider)
+        .bind(&key.predicate.claim)
+        .bind(key.predicate.operator.as_str())
+        .bind(&key.predicate.value)
+        .fetch_all(&self.pool)
+        .await?;
+        match (rows.is_empty(), version) {
+            (false, Some(version)) => Ok(Versioned::new(
+                attachment_from_rows(rows.iter().map(|row| (row.get(0), row.get(1), row.get(2)))),
+                version.get::<i64, _>(0) as u64,
+            )),
+            _ => Err(RepositoryError::NotFound),
+        }
+    }
+
+    async fn upsert_if(
+        &self,
+        key: ClaimAttachmentKey,
+        entity: PolicyAttachment,
+        precondition: Precondition,
+    ) -> Result<u64, Self::Error> {
+        // Attachments are merged with the existing ones, so the policies and roles are inserted in a single transaction
+        let mut transaction = self.pool.begin().await?;
+        let (version, current) = Self::increment_version(
+            &mut transaction,
+            &CLAIM_ATTACHMENTS,
+            &[
+                &key.identity_provider,
+                &key.predicate.claim,
+                key.predicate.operator.as_str(),
+                &key.predicate.value,
+            ],
+        )
+        .await?;
+        precondition.check(current)?;
+        for policy_id in entity.policies {
+            sqlx::query(
+                "INSERT INTO claim_attachments (identity_provider, claim, operator, value, policy_id, revision) VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT (identity_provider, claim, operator, value, policy_id) DO UPDATE SET revision = excluded.revision",
+            )
+            .bind(&key.identity_provider)
+            .bind(&key.predicate.claim)
+            .bind(key.predicate.operator.as_str())
+            .bind(&key.predicate.value)
+            .bind(&policy_id)
+            .bind(entity.revisions.get(&policy_id).map(|r| *r as i64))
+            .execute(&mut *transaction)
+            .await?;
+        }
+        for role_id in entity.roles {
+            sqlx::query(
+                "INSERT INTO claim_role_attachments (identity_provider, claim, operator, value, role_id) VALUES (?, ?, ?, ?, ?) ON CONFLICT DO NOTHING",
+            )
+            .bind(&key.identity_provider)
+            .bind(&key.predicate.claim)
+            .bind(key.predicate.operator.as_str())
+            .bind(&key.predicate.value)
+            .bind(&role_id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(version)
+    }
+
+    async fn delete_if(
+        &self,
+        key: ClaimAttachmentKey,
+        precondition: Precondition,
+    ) -> Result<(), Self::Error> {
+        self.delete_versioned(
+            &CLAIM_ATTACHMENTS,
+            &[
+                &key.identity_provider,
+                &key.predicate.claim,
+                key.predicate.operator.as_str(),
+                &key.predicate.value,
+            ],
+            precondition,
+        )
+        .await
+    }
+
+    async fn list(
+        &self,
+        query: ListQuery<ClaimAttachmentKey>,
+    ) -> Result<Page<ClaimAttachmentKey, PolicyAttachment>, Self::Error> {
+        // The page is selected from the versions of the existing attachments, and then joined with the attachment rows
+        let after = query.after.as_ref();
+        let rows = sqlx::query(
+            r#"
+            WITH page AS (
+                SELECT identity_provider, claim, operator, value
+                FROM claim_attachment_versions v
+                WHERE (?1 IS NULL OR (identity_provider, claim, operator, value) > (?1, ?2, ?3, ?4))
+                  AND (?5 IS NULL OR identity_provider = ?5)
+                  AND (?6 IS NULL OR EXISTS (
+                      SELECT 1 FROM claim_attachments a
+                      WHERE a.identity_provider = v.identity_provider AND a.claim = v.claim
+                        AND a.operator = v.operator AND a.value = v.value AND a.policy_id = ?6))
+                  AND (?7 IS NULL OR EXISTS (
+                      SELECT 1 FROM claim_role_attachments a
+                      WHERE a.identity_provider = v.identity_provider AND a.claim = v.claim
+                        AND a.operator = v.operator AND a.value = v.value AND a.role_id = ?7))
+                  AND (EXISTS (
+                           SELECT 1 FROM claim_attachments a
+                           WHERE a.identity_provider = v.identity_provider AND a.claim = v.claim
+                             AND a.operator = v.operator AND a.value = v.value)
+                       OR EXISTS (
+                           SELECT 1 FROM claim_role_attachments a
+                           WHERE a.identity_provider = v.identity_provider AND a.claim = v.claim
+                             AND a.operator = v.operator AND a.value = v.value))
+                ORDER BY identity_provider, claim, operator, value
+                LIMIT ?8
+            )
+            SELECT page.identity_provider, page.claim, page.operator, page.value, 'policy', a.policy_id, a.revision
+            FROM page
+            JOIN claim_attachments a ON a.identity_provider = page.identity_provider AND a.claim = page.claim
+                AND a.operator = page.operator AND a.value = page.value
+            UNION ALL
+            SELECT page.identity_provider, page.claim, page.operator, page.value, 'role', a.role_id, NULL
+            FROM page
+            JOIN claim_role_attachments a ON a.identity_provider = page.identity_provider AND a.claim = page.claim
+                AND a.operator = page.operator AND a.value = page.value
+            ORDER BY 1, 2, 3, 4
+            "#,
+        )
+        .bind(after.map(|key| &key.identity_provider))
+        .bind(after.map(|key| &key.predicate.claim))
+        .bind(after.map(|key| key.predicate.operator.as_str()))
+        .bind(after.map(|key| &key.predicate.value))
+        .bind(&query.filter.identity_provider)
+        .bind(&query.filter.policy)
+        .bind(&query.filter.role)
+        .bind((query.limit + 1) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let rows = rows
+            .iter()
+            .map(|row| {
+                let predicate = claim_predicate_from_row(row.get(1), row.get(2), row.get(3))?;
+                Ok((
+                    ClaimAttachmentKey::new(row.get(0), predicate),
+                    (row.get(4), row.get(5), row.get(6)),
+                ))
+            })
+            .collect::<Result<Vec<_>, RepositoryError>>()?;
+        Ok(Page::new(group_attachment_rows(rows), query.limit))
+    }
+}
+
+#[async_trait]
+impl ClaimAttachmentStore for SqliteRepository {
+    async fn list_by_provider(
+        &self,
+        identity_provider: &str,
+    ) -> Result<Vec<ClaimAttachment>, RepositoryError> {
+        let rows = sqlx::query(
+            "SELECT claim, operator, value, 'policy', policy_id, revision FROM claim_attachments WHERE identity_provider = ?1 UNION ALL SELECT claim, operator, value, 'role', role_id, NULL FROM claim_role_attachments WHERE identity_provider = ?1",
+        )
+        .bind(identity_provider.to_lowercase())
+        .fetch_all(&self.pool)
+        .await?;
+        let entries = rows
+            .into_iter()
+            .map(|row| {
+                let predicate = claim_predicate_from_row(row.get(0), row.get(1), row.get(2))?;
+                Ok((
+                    predicate,
+                    attachment_from_rows([(row.get(3), row.get(4), row.get(5))]),
+                ))
+            })
+            .collect::<Result<Vec<_>, RepositoryError>>()?;
+        Ok(group_claim_attachments(entries))
+    }
+}
+
+#[async_trait]
+impl ExpiringRepository<Revocation> for SqliteRepository {
+    type Error = RepositoryError;
+
+    async fn insert(&self, entity: Revocation) -> Result<(), Self::Error> {
+        let (target_type, identity_provider, target_id) = revocation_key(&entity.target);
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM revocations WHERE expires_at <= ?")
+            .bind(entity.revoked_at as i64)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            "INSERT INTO revocations (target_type, identity_provider, target_id, revoked_at, expires_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT (target_type, identity_provider, target_id) DO UPDATE SET revoked_at = excluded.revoked_at, expires_at = excluded.expires_at",
+        )
+        .bind(target_type)
+        .bind(identity_provider)
+        .bind(target_id)
+        .bind(entity.revoked_at as i64)
+        .bind(entity.expires_at as i64)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn list_active(&self, now: u64) -> Result<Vec<Revocation>, Self::Error> {
+        let rows = sqlx::query(
+            "SELECT target_type, identity_provider, target_id, revoked_at, expires_at FROM revocations WHERE expires_at > ?",
+        )
+        .bind(now as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                revocation_from_row(row.get(0), row.get(1), row.get(2), row.get(3), row.get(4))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl RefreshTokenStore for SqliteRepository {
+    type Error = RepositoryError;
+
+    async fn insert(&self, token: RefreshToken) -> Result<(), Self::Error> {
+        let mut transaction = self.pool.begin().await?;
+        Self::insert_refresh_token(&mut transaction, &token).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn get_by_hash(&self, token_hash: &str) -> Result<RefreshToken, Self::Error> {
+        let row = sqlx::query(
+            "SELECT token_hash, family_id, identity_provider, user_id, issued_at, expires_at, used, policies, claims FROM refresh_tokens WHERE token_hash = ?",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound)?;
+        Ok(RefreshToken {
+            token_hash: row.get(0),
+            family_id: row.get(1),
+            identity_provider: row.get(2),
+            user_id: row.get(3),
+            policies: row.get::<Option<String>, _>(7).map(|p| decode_policies(&p)),
+            claims: decode_claims(row.get(8))?,
+            issued_at: row.get::<i64, _>(4) as u64,
+            expires_at: row.get::<i64, _>(5) as u64,
+            used: row.get(6),
+        })
+    }
+
+    async fn rotate(&self, token_hash: &str, rotated: RefreshToken) -> Result<(), Self::Error> {
+        let mut transaction = self.pool.begin().await?;
+        // Only one of the concurrent requests presenting the same token can flip the flag
+        let consumed = sqlx::query(
+            "UPDATE refresh_tokens SET used = TRUE WHERE token_hash = ? AND used = FALSE",
+        )
+        .bind(token_hash)
+        .execute(&mut *transaction)
+        .await?;
+        if consumed.rows_affected() == 0 {
+            let exists: bool =
+                sqlx::query("SELECT EXISTS (SELECT 1 FROM refresh_tokens WHERE token_hash = ?)")
+                    .bind(token_hash)
+                    .fetch_one(&mut *transaction)
+                    .await?
+                    .get(0);
+            return Err(match exists {
+                true => RepositoryError::PreconditionFailed,
+                false => RepositoryError::NotFound,
+            });
+        }
+        Self::insert_refresh_token(&mut transaction, &rotated).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_family(&self, family_id: &str) -> Result<(), Self::Error> {
+        sqlx::query("DELETE FROM refresh_tokens WHERE family_id = ?")
+            .bind(family_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+impl SqliteRepository {
+    /// Stores a new refresh token and drops the tokens that expired before it was issued
+    async fn insert_refresh_token(
+        transaction: &mut Transaction<'_, Sqlite>,
+        token: &RefreshToken,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query("DELETE FROM refresh_tokens WHERE expires_at <= ?")
+            .bind(token.issued_at as i64)
+            .execute(&mut **transaction)
+            .await?;
+        sqlx::query(
+            "INSERT INTO refresh_tokens (token_hash, family_id, identity_provider, user_id, policies, claims, issued_at, expires_at, used) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&token.token_hash)
+        .bind(&token.family_id)
+        .bind(&token.identity_provider)
+        .bind(&token.user_id)
+        .bind(token.policies.as_ref().map(encode_policies))
+        .bind(encode_claims(&token.claims)?)
+        .bind(token.issued_at as i64)
+        .bind(token.expires_at as i64)
+        .bind(token.used)
+        .execute(&mut **transaction)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::external::claim_attachment::{ClaimOperator, ClaimPredicate};
+    use crate::models::external::identity_provider::ExternalIdentityProvider;
+    use crate::models::internal::revocation::RevocationTarget;
+    use crate::services::base::upsert_repository::{ListFilter, VersionMatch};
+    use crate::services::repositories::tests::{
+        assert_identity_keys_are_case_insensitive, assert_versions_increase_across_deletes,
+    };
+    use rstest::rstest;
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    /// Every repository has its own in-memory database, shared by the connections of its pool
+    async fn repository() -> SqliteRepository {
+        SqliteRepository::connect("sqlite::memory:").await.unwrap()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_policy_round_trip() {
+        let repository = repository().await;
+        let key = "policy".to_string();
+
+        repository
+            .upsert(
+                key.clone(),
+                Policy::new("permit(principal, action, resource);".to_string()),
+            )
+            .await
+            .unwrap();
+        repository
+            .upsert(
+                key.clone(),
+                Policy::new("forbid(principal, action, resource);".to_string()),
+            )
+            .await
+            .unwrap();
+        let policy: Policy = repository.get(key.clone()).await.unwrap();
+        assert_eq!(policy.content, "forbid(principal, action, resource);");
+
+        UpsertRepository::<Policy, String>::delete(&repository, key.clone())
+            .await
+            .unwrap();
+        let result: Result<Policy, _> = repository.get(key).await;
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_attachments_are_listed_in_pages() {
+        let repository = repository().await;
+        let provider = "provider".to_string();
+        let identity = |user_id: &str| ExternalIdentity::new(provider.clone(), user_id.to_string());
+        for (user_id, attachment) in [
+            ("alice", PolicyAttachment::single("reader".to_string())),
+            ("alice", PolicyAttachment::role("admins".to_string())),
+            ("bob", PolicyAttachment::single("writer".to_string())),
+            (
+                "carol",
+                PolicyAttachment::pinned("reader".to_string(), Some(2)),
+            ),
+        ] {
+            repository
+                .upsert(identity(user_id), attachment)
+                .await
+                .unwrap();
+        }
+        repository
+            .upsert(
+                ExternalIdentity::new("other".to_string(), "dave".to_string()),
+                PolicyAttachment::single("reader".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let mut query = ListQuery {
+            after: None,
+            limit: 1,
+            filter: ListFilter {
+                identity_provider: Some(provider.clone()),
+                policy: Some("reader".to_string()),
+                ..ListFilter::default()
+            },
+        };
+        let first: Page<ExternalIdentity, PolicyAttachment> =
+            repository.list(query.clone()).await.unwrap();
+        assert!(first.more);
+        assert_eq!(first.items.len(), 1);
+        assert_eq!(first.items[0].0, identity("alice"));
+        assert_eq!(
+            first.items[0].1.roles,
+            HashSet::from(["admins".to_string()])
+        );
+
+        query.after = Some(first.items[0].0.clone());
+        let second: Page<ExternalIdentity, PolicyAttachment> =
+            repository.list(query).await.unwrap();
+        assert!(!second.more);
+        assert_eq!(second.items.len(), 1);
+        assert_eq!(second.items[0].0, identity("carol"));
+        assert_eq!(
+            second.items[0].1.revisions,
+            HashMap::from([("reader".to_string(), 2)])
+        );
+
+        let query = ListQuery {
+            after: None,
+            limit: 10,
+            filter: ListFilter {
+                identity_provider: Some(provider.clone()),
+                prefix: Some("b".to_string()),
+                ..ListFilter::default()
+            },
+        };
+        let page: Page<ExternalIdentity, PolicyAttachment> = repository.list(query).await.unwrap();
+        let users: Vec<&str> = page
+            .items
+            .iter()
+            .map(|(key, _)| key.user_id.as_str())
+            .collect();
+        assert_eq!(users, vec!["bob"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_policies_are_listed_by_prefix() {
+        let repository = repository().await;
+        for id in ["lake-writer", "lake-reader", "catalog-reader"] {
+            repository
+                .upsert(id.to_string(), Policy::new(id.to_string()))
+                .await
+                .unwrap();
+        }
+
+        let query = ListQuery {
+            after: Some("lake-reader".to_string()),
+            limit: 10,
+            filter: ListFilter {
+                prefix: Some("lake-".to_string()),
+                ..ListFilter::default()
+            },
+        };
+        let page: Page<String, Policy> = repository.list(query).await.unwrap();
+        assert!(!page.more);
+        let ids: Vec<&str> = page.items.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["lake-writer"]);
+        assert_eq!(page.items[0].1.content, "lake-writer");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_conditional_writes() {
+        let repository = repository().await;
+        let key = "readers".to_string();
+        let role = Role {
+            policies: HashSet::from(["first".to_string()]),
+        };
+        let create_only = Precondition {
+            if_match: None,
+            if_none_match: Some(VersionMatch::Any),
+        };
+        let if_version = |version| Precondition {
+            if_match: Some(VersionMatch::Versions(vec![version])),
+            if_none_match: None,
+        };
+
+        let version = repository
+            .upsert_if(key.clone(), role.clone(), create_only.clone())
+            .await
+            .unwrap();
+        assert_eq!(version, 1);
+        assert!(matches!(
+            repository
+                .upsert_if(key.clone(), role.clone(), create_only)
+                .await,
+            Err(RepositoryError::PreconditionFailed)
+        ));
+        let version = repository
+            .upsert_if(key.clone(), role.clone(), if_version(1))
+            .await
+            .unwrap();
+        assert_eq!(version, 2);
+        let stored: Versioned<Role> = repository.get_versioned(key.clone()).await.unwrap();
+        assert_eq!(stored.version, 2);
+
+        assert!(matches!(
+            UpsertRepository::<Role, String>::delete_if(&repository, key.clone(), if_version(1))
+                .await,
+            Err(RepositoryError::PreconditionFailed)
+        ));
+        UpsertRepository::<Role, String>::delete_if(&repository, key.clone(), if_version(2))
+            .await
+            .unwrap();
+        assert!(matches!(
+            repository.upsert_if(key, role, if_version(2)).await,
+            Err(RepositoryError::PreconditionFailed)
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_versions_increase_across_deletes() {
+        let repository = repository().await;
+        let user = "recreated".to_string();
+
+        assert_versions_increase_across_deletes::<ExternalIdentity, (String, String), _>(
+            &repository,
+            ("provider".to_string(), user.clone()),
+            ExternalIdentity::new("provider".to_string(), user.clone()),
+        )
+        .await;
+        assert_versions_increase_across_deletes::<Role, String, _>(
+            &repository,
+            user.clone(),
+            Role {
+                policies: HashSet::from(["reader".to_string()]),
+            },
+        )
+        .await;
+        assert_versions_increase_across_deletes::<PolicyAttachment, ExternalIdentity, _>(
+            &repository,
+            ExternalIdentity::new("provider".to_string(), user.clone()),
+            PolicyAttachment::single("reader".to_string()),
+        )
+        .await;
+        assert_versions_increase_across_deletes::<PolicyAttachment, ClaimAttachmentKey, _>(
+            &repository,
+            ClaimAttachmentKey::new(
+                "provider".to_string(),
+                ClaimPredicate {
+                    claim: "groups".to_string(),
+                    operator: ClaimOperator::Contains,
+                    value: user,
+                },
+            ),
+            PolicyAttachment::single("reader".to_string()),
+        )
+        .await;
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_policy_revisions() {
+        let repository = repository().await;
+        let key = "policy".to_string();
+        let change = PolicyChange::new("provider:admin".to_string(), Some("initial".to_string()));
+
+        let first = repository
+            .upsert_revision(
+                &key,
+                Policy::new("first".to_string()),
+                change,
+                Precondition::default(),
+            )
+            .await
+            .unwrap();
+        repository
+            .upsert(key.clone(), Policy::template("second".to_string()))
+            .await
+            .unwrap();
+        UpsertRepository::<Policy, String>::delete(&repository, key.clone())
+            .await
+            .unwrap();
+
+        let revisions = repository.list_revisions(&key).await.unwrap();
+        let summary: Vec<(u64, &str, bool, Option<&str>)> = revisions
+            .iter()
+            .map(|r| {
+                (
+                    r.revision,
+                    r.policy.content.as_str(),
+                    r.policy.template,
+                    r.author.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, "first", false, Some("provider:admin")),
+                (2, "second", true, None)
+            ]
+        );
+        assert_eq!(first.reason.as_deref(), Some("initial"));
+
+        let restored = repository
+            .upsert_revision(
+                &key,
+                first.policy,
+                PolicyChange::default(),
+                Precondition::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(restored.revision, 3);
+        let policy: Policy = repository.get(key.clone()).await.unwrap();
+        assert_eq!(policy.content, "first");
+        assert!(matches!(
+            repository.get_revision(&key, 4).await,
+            Err(RepositoryError::NotFound)
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_concurrent_policy_revisions() {
+        // Transactions of an in-memory database lock whole tables, so a database file is used to test
+        // how concurrent transactions wait for each other
+        let path = std::env::temp_dir().join(format!(
+            "boxer-test-{}-{}.db",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let repository = SqliteRepository::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+
+        let upserts = (0..8).map(|i| {
+            repository.upsert_revision(
+                "policy",
+                Policy::new(format!("policy {}", i)),
+                PolicyChange::default(),
+                Precondition::default(),
+            )
+        });
+        let results = futures_util::future::join_all(upserts).await;
+        let mut revisions: Vec<u64> = results
+            .into_iter()
+            .map(|result| result.unwrap().revision)
+            .collect();
+        revisions.sort();
+        assert_eq!(revisions, (1..=8).collect::<Vec<u64>>());
+        repository.pool.close().await;
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_pinned_policy_attachments() {
+        let repository = repository().await;
+        let identity = ExternalIdentity::new("provider".to_string(), "user".to_string());
+
+        repository
+            .upsert(
+                identity.clone(),
+                PolicyAttachment::pinned("first".to_string(), Some(2)),
+            )
+            .await
+            .unwrap();
+        let attachment: PolicyAttachment = repository.get(identity.clone()).await.unwrap();
+        assert_eq!(
+            attachment.revisions,
+            HashMap::from([("first".to_string(), 2)])
+        );
+
+        repository
+            .upsert(
+                identity.clone(),
+                PolicyAttachment::single("first".to_string()),
+            )
+            .await
+            .unwrap();
+        let attachment: PolicyAttachment = repository.get(identity).await.unwrap();
+        assert!(attachment.revisions.is_empty());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_identity_keys_are_case_insensitive() {
+        assert_identity_keys_are_case_insensitive(&repository().await, "Provider").await;
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_constraint_violations_hide_database_messages() {
+        let repository = repository().await;
+        let insert = "INSERT INTO roles (id, policies) VALUES ('readers', '')";
+        sqlx::query(insert).execute(&repository.pool).await.unwrap();
+
+        let error = sqlx::query(insert)
+            .execute(&repository.pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            RepositoryError::from(error),
+            RepositoryError::Conflict(reason) if reason == "Entity already exists"
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_role_round_trip() {
+        let repository = repository().await;
+        let key = "readers".to_string();
+        let role = |policies: &[&str]| Role {
+            policies: policies.iter().map(|p| p.to_string()).collect(),
+        };
+
+        repository
+            .upsert(key.clone(), role(&["first", "second"]))
+            .await
+            .unwrap();
+        repository
+            .upsert(key.clone(), role(&["third"]))
+            .await
+            .unwrap();
+        let stored: Role = repository.get(key.clone()).await.unwrap();
+        assert_eq!(stored.policies, HashSet::from(["third".to_string()]));
+
+        UpsertRepository::<Role, String>::delete(&repository, key.clone())
+            .await
+            .unwrap();
+        let result: Result<Role, _> = repository.get(key).await;
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_policy_attachments_are_merged() {
+        let repository = repository().await;
+        let identity = ExternalIdentity::new("provider".to_string(), "user".to_string());
+
+        repository
+            .upsert(
+                identity.clone(),
+                PolicyAttachment::single("first".to_string()),
+            )
+            .await
+            .unwrap();
+        repository
+            .upsert(
+                identity.clone(),
+                PolicyAttachment::single("second".to_string()),
+            )
+            .await
+            .unwrap();
+        repository
+            .upsert(
+                identity.clone(),
+                PolicyAttachment::role("reader".to_string()),
+            )
+            .await
+            .unwrap();
+        let attachment: PolicyAttachment = repository.get(identity.clone()).await.unwrap();
+        assert_eq!(
+            attachment.policies,
+            HashSet::from(["first".to_string(), "second".to_string()])
+        );
+        assert_eq!(attachment.roles, HashSet::from(["reader".to_string()]));
+
+        UpsertRepository::<PolicyAttachment, ExternalIdentity>::delete(
+            &repository,
+            identity.clone(),
+        )
+        .await
+        .unwrap();
+        let result: Result<PolicyAttachment, _> = repository.get(identity).await;
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_claim_attachments_are_listed_by_provider() {
+        let repository = repository().await;
+        let predicate = |claim: &str, operator, value: &str| ClaimPredicate {
+            claim: claim.to_string(),
+            operator,
+            value: value.to_string(),
+        };
+        let groups = predicate("groups", ClaimOperator::Contains, "admins");
+        let tenant = predicate("tid", ClaimOperator::Equals, "tenant");
+        for (provider, predicate, policy) in [
+            ("Provider", &groups, "first"),
+            ("provider", &groups, "second"),
+            ("provider", &tenant, "first"),
+            ("other", &tenant, "third"),
+        ] {
+            repository
+                .upsert(
+                    ClaimAttachmentKey::new(provider.to_string(), predicate.clone()),
+                    PolicyAttachment::single(policy.to_string()),
+                )
+                .await
+                .unwrap();
+        }
+
+        repository
+            .upsert(
+                ClaimAttachmentKey::new("provider".to_string(), groups.clone()),
+                PolicyAttachment::role("reader".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let attachments = repository.list_by_provider("PROVIDER").await.unwrap();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0].predicate, groups);
+        assert_eq!(
+            attachments[0].attachment.policies,
+            HashSet::from(["first".to_string(), "second".to_string()])
+        );
+        assert_eq!(
+            attachments[0].attachment.roles,
+            HashSet::from(["reader".to_string()])
+        );
+        assert_eq!(attachments[1].predicate, tenant);
+        assert_eq!(
+            attachments[1].attachment.policies,
+            HashSet::from(["first".to_string()])
+        );
+
+        let query = ListQuery {
+            after: None,
+            limit: 10,
+            filter: ListFilter {
+                policy: Some("first".to_string()),
+                ..ListFilter::default()
+            },
+        };
+        let page: Page<ClaimAttachmentKey, PolicyAttachment> =
+            repository.list(query).await.unwrap();
+        let keys: Vec<ClaimAttachmentKey> = page.items.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                ClaimAttachmentKey::new("provider".to_string(), groups.clone()),
+                ClaimAttachmentKey::new("provider".to_string(), tenant.clone()),
+            ]
+        );
+        assert_eq!(page.items[0].1.roles, HashSet::from(["reader".to_string()]));
+
+        UpsertRepository::<PolicyAttachment, ClaimAttachmentKey>::delete(
+            &repository,
+            ClaimAttachmentKey::new("provider".to_string(), groups),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            repository.list_by_provider("provider").await.unwrap().len(),
+            1
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_expired_revocations_are_dropped() {
+        let repository = repository().await;
+        let revocation = |target, revoked_at| Revocation {
+            target,
+            revoked_at,
+            expires_at: revoked_at + 100,
+        };
+        let identity = RevocationTarget::Identity {
+            identity_provider: "provider".to_string(),
+            user_id: "user".to_string(),
+        };
+        let token = RevocationTarget::Token {
+            token_id: "token".to_string(),
+        };
+
+        ExpiringRepository::insert(&repository, revocation(identity.clone(), 1000))
+            .await
+            .unwrap();
+        ExpiringRepository::insert(&repository, revocation(identity.clone(), 1050))
+            .await
+            .unwrap();
+        ExpiringRepository::insert(&repository, revocation(token.clone(), 1200))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            repository.list_active(1140).await.unwrap(),
+            vec![revocation(token, 1200)]
+        );
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM revocations")
+            .fetch_one(&repository.pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_refresh_token_reuse_is_detected() {
+        let repository = repository().await;
+        let provider = ExternalIdentityProvider::from("Provider".to_string());
+        let identity = ExternalIdentity::new(provider.name(), "user".to_string());
+        let claims = HashMap::from([("groups".to_string(), serde_json::json!(["admins"]))]);
+        let (_, first) = RefreshToken::generate(
+            &provider,
+            &identity,
+            claims,
+            None,
+            Some(BTreeSet::from(["read".to_string(), "write".to_string()])),
+            SystemTime::now(),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+        let (_, second) = RefreshToken::generate(
+            &provider,
+            &identity,
+            HashMap::new(),
+            Some(first.family_id.clone()),
+            None,
+            SystemTime::now(),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+        RefreshTokenStore::insert(&repository, first.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            RefreshTokenStore::get_by_hash(&repository, &first.token_hash)
+                .await
+                .unwrap(),
+            first
+        );
+
+        repository
+            .rotate(&first.token_hash, second.clone())
+            .await
+            .unwrap();
+        assert!(
+            RefreshTokenStore::get_by_hash(&repository, &first.token_hash)
+                .await
+                .unwrap()
+                .used
+        );
+        assert_eq!(
+            RefreshTokenStore::get_by_hash(&repository, &second.token_hash)
+                .await
+                .unwrap(),
+            second
+        );
+        assert!(matches!(
+            repository.rotate(&first.token_hash, second.clone()).await,
+            Err(RepositoryError::PreconditionFailed)
+        ));
+
+        repository.delete_family(&first.family_id).await.unwrap();
+        assert!(matches!(
+            RefreshTokenStore::get_by_hash(&repository, &second.token_hash).await,
+            Err(RepositoryError::NotFound)
+        ));
+        assert!(matches!(
+            repository.rotate(&second.token_hash, first).await,
+            Err(RepositoryError::NotFound)
+        ));
+    }
+}
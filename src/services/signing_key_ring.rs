@@ -0,0 +1,258 @@
+use crate::models::internal::signing_key::{SigningKey, SigningKeyState};
+use anyhow::bail;
+use async_trait::async_trait;
+use log::info;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+
+/// Creates a new signing key ring.
+/// Keys that are no longer active stay published for `retirement_period`, which should be
+/// at least the lifetime of the longest-living token issued by the service.
+pub fn new(retirement_period: Duration) -> SigningKeyRing {
+    SigningKeyRing::new(retirement_period)
+}
+
+/// Read-only interface for the signing keys.
+#[async_trait]
+pub trait SigningKeyProvider {
+    /// Returns the key used to sign new tokens.
+    async fn get_active_key(&self) -> Result<Arc<SigningKey>, anyhow::Error>;
+
+    /// Returns the keys that should be published to verify the issued tokens.
+    async fn get_verification_keys(&self) -> Vec<Arc<SigningKey>>;
+}
+
+/// Write-only interface for the signing keys.
+#[async_trait]
+pub trait SigningKeyManager {
+    /// Replaces the content of the key ring with the provided keys and their requested states.
+    /// Keys that were active before the update are kept published until their retirement period ends,
+    /// even if they are retired or removed from the update.
+    async fn update(&self, keys: Vec<(SigningKey, SigningKeyState)>) -> Result<(), anyhow::Error>;
+}
+
+struct SigningKeyEntry {
+    key: Arc<SigningKey>,
+    state: SigningKeyState,
+    retire_at: Option<SystemTime>,
+}
+
+impl SigningKeyEntry {
+    fn effective_state(&self, now: SystemTime) -> SigningKeyState {
+        match (self.state, self.retire_at) {
+            (SigningKeyState::Retiring, Some(retire_at)) if retire_at <= now => {
+                SigningKeyState::Retired
+            }
+            (state, _) => state,
+        }
+    }
+}
+
+pub struct SigningKeyRing {
+    keys: RwLock<HashMap<String, SigningKeyEntry>>,
+    retirement_period: Duration,
+}
+
+#[async_trait]
+impl SigningKeyProvider for SigningKeyRing {
+    async fn get_active_key(&self) -> Result<Arc<SigningKey>, anyhow::Error> {
+        let read_guard = self.keys.read().await;
+        match (*read_guard)
+            .values()
+            .find(|entry| entry.state == SigningKeyState::Active)
+        {
+            Some(entry) => Ok(Arc::clone(&entry.key)),
+            None => bail!("No active signing key found"),
+        }
+    }
+
+    async fn get_verification_keys(&self) -> Vec<Arc<SigningKey>> {
+        let now = SystemTime::now();
+        let read_guard = self.keys.read().await;
+        (*read_guard)
+            .values()
+            .filter(|entry| entry.effective_state(now) != SigningKeyState::Retired)
+            .map(|entry| Arc::clone(&entry.key))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl SigningKeyManager for SigningKeyRing {
+    async fn update(&self, keys: Vec<(SigningKey, SigningKeyState)>) -> Result<(), anyhow::Error> {
+        let active_keys = keys
+            .iter()
+            .filter(|(_, state)| *state == SigningKeyState::Active)
+            .count();
+        if active_keys != 1 {
+            bail!(
+                "Exactly one active signing key is required, found {}",
+                active_keys
+            );
+        }
+
+        let now = SystemTime::now();
+        let mut write_guard = self.keys.write().await;
+        let mut previous = std::mem::take(&mut *write_guard);
+        for (key, requested_state) in keys {
+            let kid = key.kid.clone();
+            let entry = self.transition(previous.remove(&kid), key, requested_state, now);
+            (*write_guard).insert(kid, entry);
+        }
+
+        // Keys removed from the ring are kept until they can be safely retired
+        for (kid, entry) in previous {
+            let state = entry.effective_state(now);
+            if state == SigningKeyState::Active || state == SigningKeyState::Retiring {
+                let key = Arc::clone(&entry.key);
+                let entry = self.transition(Some(entry), key, SigningKeyState::Retired, now);
+                (*write_guard).insert(kid, entry);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SigningKeyRing {
+    fn new(retirement_period: Duration) -> Self {
+        SigningKeyRing {
+            keys: RwLock::new(HashMap::new()),
+            retirement_period,
+        }
+    }
+
+    fn transition(
+        &self,
+        previous: Option<SigningKeyEntry>,
+        key: impl Into<Arc<SigningKey>>,
+        requested_state: SigningKeyState,
+        now: SystemTime,
+    ) -> SigningKeyEntry {
+        let key = key.into();
+        let previous_state = previous.as_ref().map(|entry| entry.effective_state(now));
+        let (state, retire_at) = match (previous_state, requested_state) {
+            (_, SigningKeyState::Active) => (SigningKeyState::Active, None),
+            // A key that was signing tokens must be published until these tokens expire
+            (Some(SigningKeyState::Active), _) => (
+                SigningKeyState::Retiring,
+                Some(now + self.retirement_period),
+            ),
+            (Some(SigningKeyState::Retiring), _) => (
+                SigningKeyState::Retiring,
+                previous.and_then(|entry| entry.retire_at),
+            ),
+            (Some(SigningKeyState::Retired), SigningKeyState::Retiring) => {
+                (SigningKeyState::Retired, None)
+            }
+            (_, SigningKeyState::Retiring) => (
+                SigningKeyState::Retiring,
+                Some(now + self.retirement_period),
+            ),
+            (_, state) => (state, None),
+        };
+        if previous_state != Some(state) {
+            info!("Signing key {} is now {:?}", key.kid, state);
+        }
+        SigningKeyEntry {
+            key,
+            state,
+            retire_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::internal::signing_key::tests::generate;
+    use crate::models::internal::signing_key::SigningAlgorithm;
+    use rstest::rstest;
+
+    fn key(kid: &str) -> SigningKey {
+        let pem = generate(SigningAlgorithm::EdDsa);
+        SigningKey::from_pem(kid.to_string(), SigningAlgorithm::EdDsa, &pem).unwrap()
+    }
+
+    async fn verification_kids(ring: &SigningKeyRing) -> Vec<String> {
+        let mut kids: Vec<String> = ring
+            .get_verification_keys()
+            .await
+            .iter()
+            .map(|k| k.kid.clone())
+            .collect();
+        kids.sort();
+        kids
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_rotation_keeps_previous_key_published() {
+        let ring = new(Duration::from_secs(3600));
+        ring.update(vec![
+            (key("k1"), SigningKeyState::Active),
+            (key("k2"), SigningKeyState::Pending),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(ring.get_active_key().await.unwrap().kid, "k1");
+        assert_eq!(verification_kids(&ring).await, vec!["k1", "k2"]);
+
+        ring.update(vec![
+            (key("k1"), SigningKeyState::Retired),
+            (key("k2"), SigningKeyState::Active),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(ring.get_active_key().await.unwrap().kid, "k2");
+        assert_eq!(verification_kids(&ring).await, vec!["k1", "k2"]);
+
+        // Removing the retiring key from the ring does not unpublish it either
+        ring.update(vec![(key("k2"), SigningKeyState::Active)])
+            .await
+            .unwrap();
+        assert_eq!(verification_kids(&ring).await, vec!["k1", "k2"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_retiring_key_is_unpublished_after_retirement_period() {
+        let ring = new(Duration::ZERO);
+        ring.update(vec![(key("k1"), SigningKeyState::Active)])
+            .await
+            .unwrap();
+        ring.update(vec![
+            (key("k1"), SigningKeyState::Retiring),
+            (key("k2"), SigningKeyState::Active),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(verification_kids(&ring).await, vec!["k2"]);
+
+        ring.update(vec![(key("k2"), SigningKeyState::Active)])
+            .await
+            .unwrap();
+        assert_eq!(verification_kids(&ring).await, vec!["k2"]);
+    }
+
+    #[rstest]
+    #[case(vec![])]
+    #[case(vec![SigningKeyState::Pending])]
+    #[case(vec![SigningKeyState::Active, SigningKeyState::Active])]
+    #[tokio::test]
+    async fn test_update_requires_single_active_key(#[case] states: Vec<SigningKeyState>) {
+        let ring = new(Duration::from_secs(3600));
+        ring.update(vec![(key("k0"), SigningKeyState::Active)])
+            .await
+            .unwrap();
+
+        let keys = states
+            .into_iter()
+            .enumerate()
+            .map(|(i, state)| (key(&format!("k{}", i + 1)), state))
+            .collect();
+        assert!(ring.update(keys).await.is_err());
+        assert_eq!(ring.get_active_key().await.unwrap().kid, "k0");
+    }
+}
@@ -0,0 +1,176 @@
+use crate::models::internal::v1::introspection::{TokenDetails, TokenIntrospection};
+use crate::services::base::expiring_repository::RevocationRepository;
+use crate::services::base::upsert_repository::RepositoryError;
+use crate::services::signing_key_ring::{SigningKeyProvider, SigningKeyRing};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use jsonwebtoken::{DecodingKey, Validation};
+use jwt::Claims;
+use log::debug;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Creates a new token introspection service.
+pub fn new(
+    signing_keys: Arc<SigningKeyRing>,
+    revocations: Arc<RevocationRepository>,
+) -> TokenIntrospectionService {
+    TokenIntrospectionService {
+        signing_keys,
+        revocations,
+    }
+}
+
+/// Validates the tokens issued by `boxer-issuer` on behalf of the services that cannot validate them locally.
+#[async_trait]
+pub trait TokenIntrospector {
+    /// Verifies the signature, the expiration and the revocation status of the token.
+    /// Tokens that fail any of the checks are reported as inactive.
+    async fn introspect(&self, token: &str) -> Result<TokenIntrospection, RepositoryError>;
+}
+
+pub struct TokenIntrospectionService {
+    signing_keys: Arc<SigningKeyRing>,
+    revocations: Arc<RevocationRepository>,
+}
+
+#[async_trait]
+impl TokenIntrospector for TokenIntrospectionService {
+    async fn introspect(&self, token: &str) -> Result<TokenIntrospection, RepositoryError> {
+        let details = match self.verify(token).await {
+            Ok(details) => details,
+            Err(e) => {
+                debug!("Introspected token is not valid: {:?}", e);
+                return Ok(TokenIntrospection::inactive());
+            }
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| RepositoryError::Backend(e.into()))?;
+        let revocations = self.revocations.list_active(now.as_secs()).await?;
+        match revocations.iter().find(|r| r.revokes(&details)) {
+            Some(revocation) => {
+                debug!("Introspected token is revoked: {:?}", revocation.target);
+                Ok(TokenIntrospection::inactive())
+            }
+            None => Ok(TokenIntrospection::active(details)),
+        }
+    }
+}
+
+impl TokenIntrospectionService {
+    async fn verify(&self, token: &str) -> Result<TokenDetails, anyhow::Error> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let kid = header.kid.ok_or_else(|| anyhow!("Token has no key id"))?;
+        let key = self
+            .signing_keys
+            .get_verification_keys()
+            .await
+            .into_iter()
+            .find(|key| key.kid == kid)
+            .ok_or_else(|| anyhow!("Unknown signing key {}", kid))?;
+        let decoding_key = DecodingKey::from_jwk(&key.to_jwk())?;
+
+        // The issuer and the audience can be overridden per identity provider,
+        // so the token is trusted if it is signed by one of the published keys
+        let mut validation = Validation::new(key.algorithm.into());
+        validation.validate_aud = false;
+        validation.validate_nbf = true;
+        let claims = jsonwebtoken::decode::<Claims>(token, &decoding_key, &validation)?.claims;
+        TokenDetails::try_from(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::external::identity::Policy;
+    use crate::models::internal::revocation::{Revocation, RevocationTarget};
+    use crate::models::internal::signing_key::tests::generate;
+    use crate::models::internal::signing_key::{SigningAlgorithm, SigningKey, SigningKeyState};
+    use crate::models::internal::v1::token::InternalToken;
+    use crate::services::signing_key_ring;
+    use crate::services::signing_key_ring::SigningKeyManager;
+    use rstest::rstest;
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+    use tokio::sync::RwLock;
+
+    async fn introspector() -> (TokenIntrospectionService, Arc<SigningKeyRing>) {
+        let signing_keys = Arc::new(signing_key_ring::new(Duration::from_secs(3600)));
+        let pem = generate(SigningAlgorithm::Es256);
+        let key = SigningKey::from_pem("key".to_string(), SigningAlgorithm::Es256, &pem).unwrap();
+        signing_keys
+            .update(vec![(key, SigningKeyState::Active)])
+            .await
+            .unwrap();
+        let revocations: Arc<RevocationRepository> = Arc::new(RwLock::new(HashMap::new()));
+        (new(signing_keys.clone(), revocations), signing_keys)
+    }
+
+    async fn sign(signing_keys: &SigningKeyRing, issued_at: SystemTime) -> String {
+        let token = InternalToken::new(
+            BTreeMap::from([("policy".to_string(), Policy::new("content".to_string()))]),
+            "user".to_string(),
+            "provider".to_string(),
+            "issuer".to_string(),
+            "audience".to_string(),
+            issued_at,
+            issued_at + Duration::from_secs(600),
+        )
+        .unwrap();
+        let claims: Claims = token.try_into().unwrap();
+        let key = signing_keys.get_active_key().await.unwrap();
+        jsonwebtoken::encode(&key.header(), &claims, key.encoding_key()).unwrap()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_valid_token_is_active() {
+        let (introspector, signing_keys) = introspector().await;
+        let token = sign(&signing_keys, SystemTime::now()).await;
+
+        let result = introspector.introspect(&token).await.unwrap();
+
+        assert!(result.active);
+        let details = result.details.unwrap();
+        assert_eq!(details.sub, "provider:user");
+        assert_eq!(details.policy, "\ncontent");
+        assert_eq!(details.policy_ids, vec!["policy"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_revoked_token_is_inactive() {
+        let (introspector, signing_keys) = introspector().await;
+        let token = sign(&signing_keys, SystemTime::now() - Duration::from_secs(10)).await;
+        let revocation = Revocation::new(
+            RevocationTarget::Policy {
+                policy_id: "policy".to_string(),
+            },
+            SystemTime::now(),
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        introspector.revocations.insert(revocation).await.unwrap();
+
+        let result = introspector.introspect(&token).await.unwrap();
+
+        assert!(!result.active);
+        assert!(result.details.is_none());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_invalid_token_is_inactive() {
+        let other_keys = introspector().await.1;
+        let (introspector, signing_keys) = introspector().await;
+        let expired = sign(&signing_keys, SystemTime::now() - Duration::from_secs(3600)).await;
+        let foreign = sign(&other_keys, SystemTime::now()).await;
+
+        for token in [expired.as_str(), foreign.as_str(), "not a token"] {
+            let result = introspector.introspect(token).await.unwrap();
+            assert!(!result.active);
+        }
+    }
+}
@@ -1,34 +1,149 @@
 use crate::models::external::identity::{ExternalIdentity, Policy};
 use crate::models::external::identity_provider::ExternalIdentityProvider;
 use crate::models::external::token::ExternalToken;
-use crate::models::internal::v1::token::InternalToken;
-use crate::services::base::upsert_repository::{PolicyAttachmentRepository, PolicyRepository};
+use crate::models::internal::refresh_token::RefreshToken;
+use crate::models::internal::token_settings::TokenSettings;
+use crate::models::internal::v1::token::{InternalToken, POLICY_KEY};
+use crate::models::internal::v1::token_explanation::{
+    MatchedAttachments, PolicySize, TokenExplanation,
+};
+use crate::services::base::claim_attachment_store::ClaimAttachmentRepository;
+use crate::services::base::expiring_repository::RevocationRepository;
+use crate::services::base::policy_revision_store::PolicyRepository;
+use crate::services::base::refresh_token_store::RefreshTokenRepository;
+use crate::services::base::upsert_repository::{
+    PolicyAttachmentRepository, RepositoryError, RoleRepository,
+};
+use crate::services::external_identity_validator::{DynamicClaimsCollection, ValidatedIdentity};
 use crate::services::identity_validator_provider::{
     ExternalIdentityValidationService, ExternalIdentityValidatorProvider,
 };
-use anyhow::bail;
+use crate::services::signing_key_ring::{SigningKeyProvider, SigningKeyRing};
+use anyhow::anyhow;
 use async_trait::async_trait;
-use hmac::{Hmac, Mac};
-use jwt::{Claims, SignWithKey};
-use log::error;
-use sha2::Sha256;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use jwt::Claims;
+use log::{error, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A signed internal token together with its expiration time.
+pub struct IssuedToken {
+    pub token: String,
+    pub expires_at: SystemTime,
+
+    /// Opaque token that can be exchanged for a new internal token, if refresh tokens are enabled
+    pub refresh_token: Option<String>,
+}
+
+/// Returned when a token is requested with policies that are not attached to the identity
+#[derive(Debug)]
+pub struct PolicyNotAttached(pub Vec<String>);
+
+impl Display for PolicyNotAttached {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Policies are not attached: {}", self.0.join(", "))
+    }
+}
+
+impl std::error::Error for PolicyNotAttached {}
+
+/// The presented grant cannot be exchanged for a token, as opposed to an internal failure of the issuance.
+/// The description is returned to the client, the details are logged where the grant is rejected.
+#[derive(Debug)]
+pub struct InvalidGrant(pub &'static str);
 
+impl Display for InvalidGrant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidGrant {}
+
+/// The description of the invalid refresh tokens, whether they are unknown, used, expired or revoked
+const INVALID_REFRESH_TOKEN: InvalidGrant = InvalidGrant("Refresh token is not valid");
+
+/// The description of the external tokens that fail the validation of their identity provider
+const INVALID_SUBJECT_TOKEN: InvalidGrant = InvalidGrant("Subject token is not valid");
+
+/// Token issuance. Methods accepting `policies` issue a down-scoped token carrying only the requested policies,
+/// or all attached policies if `policies` is `None`.
 #[async_trait]
 pub trait TokenProvider {
+    /// Validates the external token and issues an internal token for its owner.
+    /// A refresh token is only stored and returned with `issue_refresh_token`, if refresh tokens are enabled,
+    /// so that every stored refresh token is handed out to the client.
     async fn issue_token(
         &self,
         external_identity_provider: ExternalIdentityProvider,
         external_token: ExternalToken,
-    ) -> Result<String, anyhow::Error>;
-    async fn generate_token(&self, identity: ExternalIdentity) -> Result<String, anyhow::Error>;
+        policies: Option<BTreeSet<String>>,
+        issue_refresh_token: bool,
+    ) -> Result<IssuedToken, anyhow::Error>;
+    async fn generate_token(
+        &self,
+        identity: ValidatedIdentity,
+        settings: TokenSettings,
+        policies: Option<BTreeSet<String>>,
+    ) -> Result<IssuedToken, anyhow::Error>;
+
+    /// Validates the external token like `issue_token` and explains how the token would be issued,
+    /// without signing it. Unlike `issue_token`, succeeds if no policies are attached.
+    async fn explain_token(
+        &self,
+        external_identity_provider: ExternalIdentityProvider,
+        external_token: ExternalToken,
+        policies: Option<BTreeSet<String>>,
+    ) -> Result<TokenExplanation, anyhow::Error>;
+
+    /// Resolves the policies a token issued to the identity would carry: the policies attached directly,
+    /// through the claim predicates satisfied by `claims` and through the attached roles, at their pinned revisions
+    /// and with the templates instantiated. Attached policies that do not exist are skipped.
+    /// The result is empty if no policies are attached.
+    async fn resolve_policies(
+        &self,
+        identity: &ExternalIdentity,
+        claims: &DynamicClaimsCollection,
+        policies: Option<BTreeSet<String>>,
+    ) -> Result<BTreeMap<String, Policy>, anyhow::Error>;
+
+    /// Exchanges the refresh token for a new internal token with re-evaluated policy attachments
+    /// and a new refresh token. Presenting an already used refresh token invalidates all refresh tokens
+    /// rotated from the same external token. The token is limited to the policies the refresh token was
+    /// issued for, and can be scoped down further with `policies`.
+    async fn refresh_token(
+        &self,
+        refresh_token: String,
+        policies: Option<BTreeSet<String>>,
+    ) -> Result<IssuedToken, anyhow::Error>;
+
+    /// Returns the settings of the tokens issued to the users of the identity provider.
+    async fn get_token_settings(
+        &self,
+        external_identity_provider: ExternalIdentityProvider,
+    ) -> Result<TokenSettings, anyhow::Error>;
+
+    /// Finds the identity provider that validates the external token, by the issuer of the token.
+    async fn find_identity_provider(
+        &self,
+        external_token: &ExternalToken,
+    ) -> Result<ExternalIdentityProvider, anyhow::Error>;
 }
 
 pub struct TokenService {
     validators: Arc<ExternalIdentityValidationService>,
     policy_attachment_repository: Arc<PolicyAttachmentRepository>,
+    claim_attachment_repository: Arc<ClaimAttachmentRepository>,
+    role_repository: Arc<RoleRepository>,
     policy_repository: Arc<PolicyRepository>,
-    sign_secret: Arc<Vec<u8>>,
+    signing_keys: Arc<SigningKeyRing>,
+    token_settings: TokenSettings,
+    refresh_tokens: Arc<RefreshTokenRepository>,
+    revocations: Arc<RevocationRepository>,
 }
 
 #[async_trait]
@@ -37,58 +152,1095 @@ impl TokenProvider for TokenService {
         &self,
         provider: ExternalIdentityProvider,
         external_token: ExternalToken,
-    ) -> Result<String, anyhow::Error> {
+        policies: Option<BTreeSet<String>>,
+        issue_refresh_token: bool,
+    ) -> Result<IssuedToken, anyhow::Error> {
         let validator = self.validators.get(provider.clone()).await?;
+        let settings = self.get_token_settings(provider.clone()).await?;
         let result = validator.validate(external_token).await;
         match result {
-            Ok(identity) => self.generate_token(identity).await,
+            Ok(validated) => {
+                let identity = validated.identity.clone();
+                let claims = validated.claims.clone();
+                let mut issued = self
+                    .generate_token(validated, settings.clone(), policies.clone())
+                    .await?;
+                if issue_refresh_token {
+                    issued.refresh_token = self
+                        .issue_refresh_token(&provider, &identity, claims, policies, &settings)
+                        .await?;
+                }
+                Ok(issued)
+            }
             Err(err) => {
-                error!(
+                warn!(
                     "Failed to validate user token against provider with name {}: {:?}",
                     provider.name(),
                     err
                 );
-                bail!(
-                    "Failed to validate user token against provider with name {}: {:?}",
-                    provider.name(),
-                    err
-                )
+                Err(INVALID_SUBJECT_TOKEN.into())
             }
         }
     }
-    async fn generate_token(&self, identity: ExternalIdentity) -> Result<String, anyhow::Error> {
-        let attachment = self
-            .policy_attachment_repository
-            .get(identity.clone())
+    async fn generate_token(
+        &self,
+        validated: ValidatedIdentity,
+        settings: TokenSettings,
+        requested: Option<BTreeSet<String>>,
+    ) -> Result<IssuedToken, anyhow::Error> {
+        let identity: ExternalIdentity = validated.identity;
+        let policies = self
+            .resolve_policies(&identity, &validated.claims, requested)
             .await?;
-        let policies = Policy::empty();
-        for p in attachment.policies {
-            let policy = self.policy_repository.get(p).await?;
-            policies.merge(policy);
+        if policies.is_empty() {
+            warn!(
+                "No policies are attached to {}/{}",
+                identity.identity_provider, identity.user_id
+            );
+            return Err(InvalidGrant("No policies are attached to the identity").into());
         }
 
-        let token = InternalToken::new(policies, identity.user_id, identity.identity_provider);
+        let (token, expires_at) =
+            Self::new_token(identity, policies, settings, validated.expires_at)?;
+        let claims: Claims = token.try_into()?;
+        let key = self.signing_keys.get_active_key().await?;
+        let token =
+            jsonwebtoken::encode(&key.header(), &claims, key.encoding_key()).map_err(|e| {
+                error!("Failed to issue token: {:?}", e);
+                anyhow::anyhow!(e)
+            })?;
+        Ok(IssuedToken {
+            token,
+            expires_at,
+            refresh_token: None,
+        })
+    }
+
+    async fn explain_token(
+        &self,
+        provider: ExternalIdentityProvider,
+        external_token: ExternalToken,
+        requested: Option<BTreeSet<String>>,
+    ) -> Result<TokenExplanation, anyhow::Error> {
+        let validator = self.validators.get(provider.clone()).await?;
+        let provider_settings = self
+            .validators
+            .get_provider_settings(provider.clone())
+            .await?;
+        let settings = self.get_token_settings(provider.clone()).await?;
+        let validated = validator.validate(external_token).await.map_err(|err| {
+            error!(
+                "Failed to validate user token against provider with name {}: {:?}",
+                provider.name(),
+                err
+            );
+            anyhow!(
+                "Failed to validate user token against provider with name {}: {:?}",
+                provider.name(),
+                err
+            )
+        })?;
+
+        let identity = validated.identity;
+        let attachments = self.match_attachments(&identity, &validated.claims).await?;
+        let selected = requested.clone().unwrap_or_else(|| attachments.policies());
+        let policies = self
+            .resolve_policies(&identity, &validated.claims, requested)
+            .await?;
+        let policy_ids: Vec<String> = policies.keys().cloned().collect();
+        let missing_policies = selected
+            .into_iter()
+            .filter(|id| !policies.contains_key(id))
+            .collect();
+        let (token, _) =
+            Self::new_token(identity.clone(), policies, settings, validated.expires_at)?;
+        let uncompressed = token.policy.content.len();
         let claims: Claims = token.try_into()?;
-        let key: Hmac<Sha256> = Hmac::new_from_slice(&self.sign_secret)?;
-        claims.sign_with_key(&key).map_err(|e| {
-            error!("Failed to issue token: {:?}", e);
-            anyhow::anyhow!(e)
+        let encoded = claims
+            .private
+            .get(POLICY_KEY)
+            .and_then(|policy| policy.as_str())
+            .unwrap_or_default();
+        let policy_size = PolicySize {
+            uncompressed,
+            compressed: STANDARD.decode(encoded)?.len(),
+            encoded: encoded.len(),
+        };
+        Ok(TokenExplanation {
+            identity,
+            user_id_claim: provider_settings.user_id_claim,
+            attachments,
+            policies: policy_ids,
+            missing_policies,
+            policy_size,
+            claims,
         })
     }
+
+    async fn resolve_policies(
+        &self,
+        identity: &ExternalIdentity,
+        claims: &DynamicClaimsCollection,
+        requested: Option<BTreeSet<String>>,
+    ) -> Result<BTreeMap<String, Policy>, anyhow::Error> {
+        let matched = self.match_attachments(identity, claims).await?;
+        let attached = matched.policies();
+        let revisions = matched.revisions();
+        let selected = match requested {
+            Some(requested) => {
+                let missing: Vec<String> = requested.difference(&attached).cloned().collect();
+                if !missing.is_empty() {
+                    return Err(PolicyNotAttached(missing).into());
+                }
+                requested
+            }
+            None => attached,
+        };
+        let mut policies = BTreeMap::new();
+        for p in selected {
+            // Revisions outlive the deleted policy, so a pinned revision is only used while the policy exists
+            let policy = match (
+                self.policy_repository.get(p.clone()).await,
+                revisions.get(&p),
+            ) {
+                (Ok(_), Some(revision)) => self
+                    .policy_repository
+                    .get_revision(&p, *revision)
+                    .await
+                    .map(|revision| revision.policy),
+                (policy, _) => policy,
+            };
+            let policy = match policy {
+                Ok(policy) => policy,
+                Err(RepositoryError::NotFound) => {
+                    warn!("Attached policy {} does not exist, skipping", p);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let policy = policy
+                .instantiate(identity, claims)
+                .map_err(|e| anyhow!("Failed to instantiate policy template {}: {}", p, e))?;
+            policies.insert(p, policy);
+        }
+        Ok(policies)
+    }
+
+    async fn refresh_token(
+        &self,
+        refresh_token: String,
+        requested: Option<BTreeSet<String>>,
+    ) -> Result<IssuedToken, anyhow::Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let token_hash = RefreshToken::hash(&refresh_token);
+        let stored = self
+            .refresh_tokens
+            .get_by_hash(&token_hash)
+            .await
+            .map_err(|e| match e {
+                RepositoryError::NotFound => {
+                    warn!("Unknown refresh token presented");
+                    anyhow!(INVALID_REFRESH_TOKEN)
+                }
+                e => anyhow!(e),
+            })?;
+        let identity = stored.identity();
+        if stored.used {
+            return Err(self.revoke_reused(&stored).await);
+        }
+        if stored.is_expired(now) {
+            warn!(
+                "Expired refresh token presented for {}/{}",
+                stored.identity_provider, stored.user_id
+            );
+            return Err(INVALID_REFRESH_TOKEN.into());
+        }
+        let revocations = self.revocations.list_active(now).await?;
+        if revocations
+            .iter()
+            .any(|r| r.revokes_identity(&identity, stored.issued_at))
+        {
+            self.refresh_tokens.delete_family(&stored.family_id).await?;
+            warn!(
+                "Revoked refresh token presented for {}/{}",
+                stored.identity_provider, stored.user_id
+            );
+            return Err(INVALID_REFRESH_TOKEN.into());
+        }
+
+        let provider = stored.provider();
+        let settings = self.get_token_settings(provider.clone()).await?;
+        let Some(lifetime) = settings.refresh_token_lifetime() else {
+            warn!("Refresh token presented while refresh tokens are disabled");
+            return Err(INVALID_REFRESH_TOKEN.into());
+        };
+        let validated = ValidatedIdentity {
+            identity: identity.clone(),
+            expires_at: None,
+            claims: stored.claims.clone(),
+        };
+        let policies = match (requested, &stored.policies) {
+            (Some(requested), Some(granted)) => {
+                let missing: Vec<String> = requested.difference(granted).cloned().collect();
+                if !missing.is_empty() {
+                    return Err(PolicyNotAttached(missing).into());
+                }
+                Some(requested)
+            }
+            (requested, granted) => requested.or(granted.clone()),
+        };
+        let mut issued = self
+            .generate_token(validated, settings.clone(), policies)
+            .await?;
+
+        // The presented token is only used up once the new tokens are issued, so a failed refresh can be retried
+        let (value, rotated) = RefreshToken::generate(
+            &provider,
+            &identity,
+            stored.claims.clone(),
+            Some(stored.family_id.clone()),
+            stored.policies.clone(),
+            SystemTime::now(),
+            lifetime,
+        )?;
+        match self.refresh_tokens.rotate(&token_hash, rotated).await {
+            Ok(()) => {}
+            Err(RepositoryError::PreconditionFailed) => {
+                return Err(self.revoke_reused(&stored).await)
+            }
+            Err(RepositoryError::NotFound) => return Err(INVALID_REFRESH_TOKEN.into()),
+            Err(e) => return Err(e.into()),
+        }
+        issued.refresh_token = Some(value);
+        Ok(issued)
+    }
+
+    async fn get_token_settings(
+        &self,
+        provider: ExternalIdentityProvider,
+    ) -> Result<TokenSettings, anyhow::Error> {
+        let provider_settings = self.validators.get_provider_settings(provider).await?;
+        Ok(self
+            .token_settings
+            .with_override(provider_settings.token.as_ref()))
+    }
+
+    async fn find_identity_provider(
+        &self,
+        external_token: &ExternalToken,
+    ) -> Result<ExternalIdentityProvider, anyhow::Error> {
+        let issuer = external_token.unverified_issuer().map_err(|e| {
+            warn!("Subject token without a readable issuer: {:?}", e);
+            INVALID_SUBJECT_TOKEN
+        })?;
+        let mut providers = self.validators.find_by_issuer(&issuer).await;
+        match providers.len() {
+            1 => Ok(providers.remove(0)),
+            0 => {
+                warn!("No identity provider accepts tokens from issuer {}", issuer);
+                Err(
+                    InvalidGrant("No identity provider accepts the issuer of the subject token")
+                        .into(),
+                )
+            }
+            _ => {
+                warn!(
+                    "Several identity providers accept tokens from issuer {}",
+                    issuer
+                );
+                Err(InvalidGrant(
+                    "Several identity providers accept the issuer of the subject token, use the provider-specific token endpoint",
+                )
+                .into())
+            }
+        }
+    }
 }
 
 impl TokenService {
+    /// Collects the attachments of the identity and the claim attachments whose predicates are satisfied
+    /// by the external token, expanding the attached roles.
+    async fn match_attachments(
+        &self,
+        identity: &ExternalIdentity,
+        claims: &DynamicClaimsCollection,
+    ) -> Result<MatchedAttachments, anyhow::Error> {
+        let mut matched = MatchedAttachments::default();
+        match self
+            .policy_attachment_repository
+            .get(identity.clone())
+            .await
+        {
+            Ok(attachment) => matched.identity = Some(attachment),
+            Err(RepositoryError::NotFound) => {}
+            Err(e) => return Err(e.into()),
+        };
+        matched.claims = self
+            .claim_attachment_repository
+            .list_by_provider(&identity.identity_provider)
+            .await?
+            .into_iter()
+            .filter(|claim_attachment| claim_attachment.predicate.matches(claims))
+            .collect();
+
+        for role_id in matched.role_ids() {
+            match self.role_repository.get(role_id.clone()).await {
+                Ok(role) => {
+                    matched.roles.insert(role_id, role);
+                }
+                Err(RepositoryError::NotFound) => {
+                    warn!("Attached role {} does not exist, skipping", role_id);
+                    matched.missing_roles.push(role_id);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(matched)
+    }
+
+    /// Creates the unsigned token for the identity, expiring with the external token if it expires earlier
+    fn new_token(
+        identity: ExternalIdentity,
+        policies: BTreeMap<String, Policy>,
+        settings: TokenSettings,
+        external_expires_at: Option<SystemTime>,
+    ) -> Result<(InternalToken, SystemTime), anyhow::Error> {
+        let issued_at = SystemTime::now();
+        let expires_at = settings.expiration(issued_at, external_expires_at);
+        let token = InternalToken::new(
+            policies,
+            identity.user_id,
+            identity.identity_provider,
+            settings.issuer(),
+            settings.audience,
+            issued_at,
+            expires_at,
+        )?;
+        Ok((token, expires_at))
+    }
+
+    /// Revokes all refresh tokens rotated from the same external token as the token presented twice,
+    /// as either the client or an attacker holds a stolen token
+    async fn revoke_reused(&self, stored: &RefreshToken) -> anyhow::Error {
+        warn!(
+            "Refresh token reuse detected for {}/{}, revoking the refresh token family",
+            stored.identity_provider, stored.user_id
+        );
+        match self.refresh_tokens.delete_family(&stored.family_id).await {
+            Ok(()) => INVALID_REFRESH_TOKEN.into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Generates and stores a refresh token, if refresh tokens are enabled
+    async fn issue_refresh_token(
+        &self,
+        provider: &ExternalIdentityProvider,
+        identity: &ExternalIdentity,
+        claims: DynamicClaimsCollection,
+        policies: Option<BTreeSet<String>>,
+        settings: &TokenSettings,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let Some(lifetime) = settings.refresh_token_lifetime() else {
+            return Ok(None);
+        };
+        let (value, record) = RefreshToken::generate(
+            provider,
+            identity,
+            claims,
+            None,
+            policies,
+            SystemTime::now(),
+            lifetime,
+        )?;
+        self.refresh_tokens.insert(record).await?;
+        Ok(Some(value))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         validators: Arc<ExternalIdentityValidationService>,
         policy_repository: Arc<PolicyRepository>,
+        role_repository: Arc<RoleRepository>,
         policy_attachment_repository: Arc<PolicyAttachmentRepository>,
-        sign_secret: Arc<Vec<u8>>,
+        claim_attachment_repository: Arc<ClaimAttachmentRepository>,
+        signing_keys: Arc<SigningKeyRing>,
+        token_settings: TokenSettings,
+        refresh_tokens: Arc<RefreshTokenRepository>,
+        revocations: Arc<RevocationRepository>,
     ) -> Self {
         TokenService {
             validators,
             policy_repository,
+            role_repository,
             policy_attachment_repository,
-            sign_secret,
+            claim_attachment_repository,
+            signing_keys,
+            token_settings,
+            refresh_tokens,
+            revocations,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::external::claim_attachment::{
+        ClaimAttachmentKey, ClaimOperator, ClaimPredicate,
+    };
+    use crate::models::external::identity::{PolicyAttachment, Role};
+    use crate::models::external::identity_provider_settings::OidcExternalIdentityProviderSettings;
+    use crate::models::internal::revocation::{Revocation, RevocationTarget};
+    use crate::models::internal::signing_key::tests::generate;
+    use crate::models::internal::signing_key::{SigningAlgorithm, SigningKey, SigningKeyState};
+    use crate::models::internal::v1::introspection::TokenDetails;
+    use crate::services::external_identity_validator::ExternalIdentityValidator;
+    use crate::services::identity_validator_provider;
+    use crate::services::repositories::in_memory::PolicyHistory;
+    use crate::services::signing_key_ring::{self, SigningKeyManager};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use rstest::rstest;
+    use std::collections::{HashMap, HashSet};
+    use std::time::Duration;
+    use tokio::sync::RwLock;
+
+    pub(crate) const PROVIDER: &str = "provider";
+
+    /// Accepts any token as a token of the user in the `admins` group of the `tenant` tenant
+    struct StaticValidator;
+
+    #[async_trait]
+    impl ExternalIdentityValidator for StaticValidator {
+        async fn validate(&self, _: ExternalToken) -> Result<ValidatedIdentity, anyhow::Error> {
+            Ok(ValidatedIdentity {
+                identity: ExternalIdentity::new(PROVIDER.to_string(), "user".to_string()),
+                expires_at: None,
+                claims: DynamicClaimsCollection::from([
+                    ("groups".to_string(), serde_json::json!(["admins"])),
+                    ("tid".to_string(), serde_json::json!("tenant")),
+                ]),
+            })
         }
     }
+
+    /// A token service together with its in-memory repositories, to arrange and inspect the stored state
+    pub(crate) struct Fixture {
+        pub(crate) service: TokenService,
+        pub(crate) validators: Arc<ExternalIdentityValidationService>,
+        pub(crate) policies: Arc<PolicyRepository>,
+        pub(crate) roles: Arc<RoleRepository>,
+        pub(crate) attachments: Arc<PolicyAttachmentRepository>,
+        pub(crate) claim_attachments: Arc<ClaimAttachmentRepository>,
+        pub(crate) revocations: Arc<RevocationRepository>,
+        pub(crate) refresh_tokens: Arc<RwLock<HashMap<String, RefreshToken>>>,
+    }
+
+    /// Creates a token service with in-memory repositories and refresh tokens enabled,
+    /// that issues tokens with the `reader` policy to every caller of `PROVIDER`.
+    pub(crate) async fn token_service() -> Fixture {
+        let validators = Arc::new(identity_validator_provider::new());
+        validators
+            .register(
+                ExternalIdentityProvider::from(PROVIDER.to_string()),
+                OidcExternalIdentityProviderSettings {
+                    user_id_claim: "sub".to_string(),
+                    discovery_url: "https://login.example.com".to_string(),
+                    issuers: vec!["https://login.example.com".to_string()],
+                    audiences: vec!["boxer".to_string()],
+                    token: None,
+                },
+                Arc::new(StaticValidator),
+            )
+            .await;
+
+        let policies: Arc<PolicyRepository> =
+            Arc::new(RwLock::new(HashMap::<String, PolicyHistory>::new()));
+        policies
+            .upsert(
+                "reader".to_string(),
+                Policy::new("permit(principal, action, resource);".to_string()),
+            )
+            .await
+            .unwrap();
+        let attachments: Arc<PolicyAttachmentRepository> = Arc::new(RwLock::new(HashMap::new()));
+        attachments
+            .upsert(
+                ExternalIdentity::new(PROVIDER.to_string(), "user".to_string()),
+                PolicyAttachment::single("reader".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let signing_keys = signing_key_ring::new(Duration::from_secs(3600));
+        let pem = generate(SigningAlgorithm::EdDsa);
+        let key = SigningKey::from_pem("k1".to_string(), SigningAlgorithm::EdDsa, &pem).unwrap();
+        signing_keys
+            .update(vec![(key, SigningKeyState::Active)])
+            .await
+            .unwrap();
+
+        let refresh_tokens: Arc<RwLock<HashMap<String, RefreshToken>>> = Arc::default();
+        let roles: Arc<RoleRepository> = Arc::new(RwLock::new(HashMap::new()));
+        let claim_attachments: Arc<ClaimAttachmentRepository> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let revocations: Arc<RevocationRepository> = Arc::new(RwLock::new(HashMap::new()));
+        let service = TokenService::new(
+            validators.clone(),
+            policies.clone(),
+            roles.clone(),
+            attachments.clone(),
+            claim_attachments.clone(),
+            Arc::new(signing_keys),
+            TokenSettings {
+                refresh_token_lifetime_seconds: Some(3600),
+                ..TokenSettings::default()
+            },
+            refresh_tokens.clone(),
+            revocations.clone(),
+        );
+        Fixture {
+            service,
+            validators,
+            policies,
+            roles,
+            attachments,
+            claim_attachments,
+            revocations,
+            refresh_tokens,
+        }
+    }
+
+    /// Exchanges a subject token of the user of `PROVIDER` for a token with a refresh token
+    pub(crate) async fn issue(service: &TokenService, policies: Option<&[&str]>) -> IssuedToken {
+        service
+            .issue_token(
+                ExternalIdentityProvider::from(PROVIDER.to_string()),
+                ExternalToken::from("external".to_string()),
+                policies.map(|ids| ids.iter().map(|id| id.to_string()).collect()),
+                true,
+            )
+            .await
+            .unwrap()
+    }
+
+    /// Decodes the claims of the issued token, without verifying its signature
+    pub(crate) fn details(issued: &IssuedToken) -> TokenDetails {
+        let payload = issued.token.split('.').nth(1).unwrap();
+        let claims: Claims =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        TokenDetails::try_from(claims).unwrap()
+    }
+
+    fn details_of(result: Result<IssuedToken, anyhow::Error>) -> TokenDetails {
+        details(&result.unwrap_or_else(|e| panic!("Token was not issued: {}", e)))
+    }
+
+    fn is_invalid_grant(result: Result<IssuedToken, anyhow::Error>) -> bool {
+        result.is_err_and(|e| e.downcast_ref::<InvalidGrant>().is_some())
+    }
+
+    #[rstest]
+    #[case(true, 1)]
+    #[case(false, 0)]
+    #[tokio::test]
+    async fn test_refresh_tokens_are_stored_on_request(
+        #[case] issue_refresh_token: bool,
+        #[case] stored: usize,
+    ) {
+        let Fixture {
+            service,
+            refresh_tokens,
+            ..
+        } = token_service().await;
+        let issued = service
+            .issue_token(
+                ExternalIdentityProvider::from(PROVIDER.to_string()),
+                ExternalToken::from("external".to_string()),
+                None,
+                issue_refresh_token,
+            )
+            .await
+            .unwrap();
+        assert_eq!(issued.refresh_token.is_some(), issue_refresh_token);
+        assert_eq!(refresh_tokens.read().await.len(), stored);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_failed_refresh_can_be_retried() {
+        let fixture = token_service().await;
+        let service = &fixture.service;
+        let issued = service
+            .issue_token(
+                ExternalIdentityProvider::from(PROVIDER.to_string()),
+                ExternalToken::from("external".to_string()),
+                None,
+                true,
+            )
+            .await
+            .unwrap();
+        let refresh_token = issued.refresh_token.unwrap();
+        let identity = ExternalIdentity::new(PROVIDER.to_string(), "user".to_string());
+        fixture.attachments.delete(identity.clone()).await.unwrap();
+
+        assert!(service
+            .refresh_token(refresh_token.clone(), None)
+            .await
+            .is_err());
+        fixture
+            .attachments
+            .upsert(identity, PolicyAttachment::single("reader".to_string()))
+            .await
+            .unwrap();
+        let refreshed = service.refresh_token(refresh_token, None).await.unwrap();
+        assert!(refreshed.refresh_token.is_some());
+        assert_eq!(fixture.refresh_tokens.read().await.len(), 2);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_refresh_rotates_the_refresh_token() {
+        let fixture = token_service().await;
+        let first = issue(&fixture.service, None).await.refresh_token.unwrap();
+
+        let refreshed = fixture
+            .service
+            .refresh_token(first.clone(), None)
+            .await
+            .unwrap();
+        let second = refreshed.refresh_token.clone().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(details(&refreshed).policy_ids, vec!["reader"]);
+        let stored = fixture.refresh_tokens.read().await;
+        let (first, second) = (
+            &stored[&RefreshToken::hash(&first)],
+            &stored[&RefreshToken::hash(&second)],
+        );
+        assert!(first.used);
+        assert!(!second.used);
+        assert_eq!(first.family_id, second.family_id);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_refresh_token_reuse_revokes_the_family() {
+        let fixture = token_service().await;
+        let first = issue(&fixture.service, None).await.refresh_token.unwrap();
+        let second = fixture
+            .service
+            .refresh_token(first.clone(), None)
+            .await
+            .unwrap()
+            .refresh_token
+            .unwrap();
+
+        assert!(is_invalid_grant(
+            fixture.service.refresh_token(first, None).await
+        ));
+        assert!(is_invalid_grant(
+            fixture.service.refresh_token(second, None).await
+        ));
+        assert!(fixture.refresh_tokens.read().await.is_empty());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_expired_refresh_token_is_rejected() {
+        let fixture = token_service().await;
+        let (value, stored) = RefreshToken::generate(
+            &ExternalIdentityProvider::from(PROVIDER.to_string()),
+            &ExternalIdentity::new(PROVIDER.to_string(), "user".to_string()),
+            DynamicClaimsCollection::new(),
+            None,
+            None,
+            SystemTime::now() - Duration::from_secs(7200),
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        fixture
+            .refresh_tokens
+            .write()
+            .await
+            .insert(stored.token_hash.clone(), stored);
+
+        assert!(is_invalid_grant(
+            fixture.service.refresh_token(value.clone(), None).await
+        ));
+        assert!(!fixture.refresh_tokens.read().await[&RefreshToken::hash(&value)].used);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_revoked_identity_cannot_refresh() {
+        let fixture = token_service().await;
+        let refresh_token = issue(&fixture.service, None).await.refresh_token.unwrap();
+        let revocation = Revocation::new(
+            RevocationTarget::Identity {
+                identity_provider: PROVIDER.to_string(),
+                user_id: "user".to_string(),
+            },
+            SystemTime::now(),
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        fixture.revocations.insert(revocation).await.unwrap();
+
+        assert!(is_invalid_grant(
+            fixture.service.refresh_token(refresh_token, None).await
+        ));
+        assert!(fixture.refresh_tokens.read().await.is_empty());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_refresh_cannot_widen_the_policies() {
+        let fixture = token_service().await;
+        fixture
+            .policies
+            .upsert(
+                "writer".to_string(),
+                Policy::new("permit(principal, action, resource);".to_string()),
+            )
+            .await
+            .unwrap();
+        fixture
+            .attachments
+            .upsert(
+                ExternalIdentity::new(PROVIDER.to_string(), "user".to_string()),
+                PolicyAttachment::new(HashSet::from(["reader".to_string(), "writer".to_string()])),
+            )
+            .await
+            .unwrap();
+        let refresh_token = issue(&fixture.service, Some(&["reader"]))
+            .await
+            .refresh_token
+            .unwrap();
+
+        let widened = fixture
+            .service
+            .refresh_token(
+                refresh_token.clone(),
+                Some(BTreeSet::from(["writer".to_string()])),
+            )
+            .await;
+        assert!(widened.is_err_and(|e| e.downcast_ref::<PolicyNotAttached>().is_some()));
+        let refreshed = fixture
+            .service
+            .refresh_token(refresh_token, None)
+            .await
+            .unwrap();
+        assert_eq!(details(&refreshed).policy_ids, vec!["reader"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_missing_policies_are_skipped() {
+        let fixture = token_service().await;
+        fixture
+            .attachments
+            .upsert(
+                ExternalIdentity::new(PROVIDER.to_string(), "user".to_string()),
+                PolicyAttachment::single("deleted".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let issued = issue(&fixture.service, None).await;
+        assert_eq!(details(&issued).policy_ids, vec!["reader"]);
+        let explanation = fixture
+            .service
+            .explain_token(
+                ExternalIdentityProvider::from(PROVIDER.to_string()),
+                ExternalToken::from("external".to_string()),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(explanation.policies, vec!["reader"]);
+        assert_eq!(explanation.missing_policies, vec!["deleted"]);
+    }
+
+    #[rstest]
+    #[case(None, Ok(vec!["reader", "writer"]))]
+    #[case(Some(vec!["writer"]), Ok(vec!["writer"]))]
+    #[case(Some(vec!["writer", "admin"]), Err(vec!["admin"]))]
+    #[tokio::test]
+    async fn test_down_scoped_tokens(
+        #[case] requested: Option<Vec<&str>>,
+        #[case] expected: Result<Vec<&str>, Vec<&str>>,
+    ) {
+        let fixture = token_service().await;
+        fixture
+            .policies
+            .upsert(
+                "writer".to_string(),
+                Policy::new("forbid(principal, action, resource);".to_string()),
+            )
+            .await
+            .unwrap();
+        fixture
+            .attachments
+            .upsert(
+                ExternalIdentity::new(PROVIDER.to_string(), "user".to_string()),
+                PolicyAttachment::single("writer".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let result = fixture
+            .service
+            .issue_token(
+                ExternalIdentityProvider::from(PROVIDER.to_string()),
+                ExternalToken::from("external".to_string()),
+                requested.map(|ids| ids.iter().map(|id| id.to_string()).collect()),
+                false,
+            )
+            .await;
+        match expected {
+            Ok(policy_ids) => {
+                let details = details(&result.unwrap());
+                assert_eq!(details.policy_ids, policy_ids);
+                assert_eq!(
+                    details
+                        .policy
+                        .contains("permit(principal, action, resource);"),
+                    policy_ids.contains(&"reader")
+                );
+                assert!(details
+                    .policy
+                    .contains("forbid(principal, action, resource);"));
+            }
+            Err(missing) => {
+                let e = result.err().unwrap();
+                let not_attached = e.downcast_ref::<PolicyNotAttached>().unwrap();
+                assert_eq!(not_attached.0, missing);
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(ClaimOperator::Contains, "groups", "admins", true)]
+    #[case(ClaimOperator::Contains, "groups", "readers", false)]
+    #[case(ClaimOperator::Equals, "tid", "tenant", true)]
+    #[case(ClaimOperator::Equals, "tid", "other", false)]
+    #[tokio::test]
+    async fn test_claim_attachments(
+        #[case] operator: ClaimOperator,
+        #[case] claim: &str,
+        #[case] value: &str,
+        #[case] attached: bool,
+    ) {
+        let fixture = token_service().await;
+        fixture
+            .policies
+            .upsert(
+                "admin".to_string(),
+                Policy::new("forbid(principal, action, resource);".to_string()),
+            )
+            .await
+            .unwrap();
+        fixture
+            .claim_attachments
+            .upsert(
+                ClaimAttachmentKey::new(
+                    PROVIDER.to_string(),
+                    ClaimPredicate {
+                        claim: claim.to_string(),
+                        operator,
+                        value: value.to_string(),
+                    },
+                ),
+                PolicyAttachment::single("admin".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let details = details(&issue(&fixture.service, None).await);
+        let expected = match attached {
+            true => vec!["admin", "reader"],
+            false => vec!["reader"],
+        };
+        assert_eq!(details.policy_ids, expected);
+        assert_eq!(
+            details
+                .policy
+                .contains("forbid(principal, action, resource);"),
+            attached
+        );
+
+        let scoped = fixture
+            .service
+            .issue_token(
+                ExternalIdentityProvider::from(PROVIDER.to_string()),
+                ExternalToken::from("external".to_string()),
+                Some(BTreeSet::from(["admin".to_string()])),
+                false,
+            )
+            .await;
+        match attached {
+            true => assert_eq!(details_of(scoped).policy_ids, vec!["admin"]),
+            false => assert!(scoped.is_err_and(|e| e.is::<PolicyNotAttached>())),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_roles_are_expanded() {
+        let fixture = token_service().await;
+        for (id, content) in [
+            ("writer", "forbid(principal, action, resource);"),
+            (
+                "admin",
+                "forbid(principal, action, resource) unless { false };",
+            ),
+        ] {
+            fixture
+                .policies
+                .upsert(id.to_string(), Policy::new(content.to_string()))
+                .await
+                .unwrap();
+        }
+        for (id, policies) in [("writers", "writer"), ("admins", "admin")] {
+            fixture
+                .roles
+                .upsert(
+                    id.to_string(),
+                    Role {
+                        policies: HashSet::from([policies.to_string()]),
+                    },
+                )
+                .await
+                .unwrap();
+        }
+        fixture
+            .attachments
+            .upsert(
+                ExternalIdentity::new(PROVIDER.to_string(), "user".to_string()),
+                PolicyAttachment::role("writers".to_string()),
+            )
+            .await
+            .unwrap();
+        fixture
+            .claim_attachments
+            .upsert(
+                ClaimAttachmentKey::new(
+                    PROVIDER.to_string(),
+                    ClaimPredicate {
+                        claim: "groups".to_string(),
+                        operator: ClaimOperator::Contains,
+                        value: "admins".to_string(),
+                    },
+                ),
+                PolicyAttachment::role("admins".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let expanded = details(&issue(&fixture.service, None).await);
+        assert_eq!(expanded.policy_ids, vec!["admin", "reader", "writer"]);
+        assert!(expanded
+            .policy
+            .contains("forbid(principal, action, resource);"));
+        assert!(expanded.policy.contains("unless { false }"));
+
+        fixture
+            .roles
+            .upsert(
+                "writers".to_string(),
+                Role {
+                    policies: HashSet::new(),
+                },
+            )
+            .await
+            .unwrap();
+        let issued = details(&issue(&fixture.service, None).await);
+        assert_eq!(issued.policy_ids, vec!["admin", "reader"]);
+    }
+
+    #[rstest]
+    #[case(
+        r#"permit(principal, action, resource) when { resource.path like "/{{identity_provider}}/{{claims.tid}}/{{user_id}}/*" };"#,
+        Some(r#"resource.path like "/provider/tenant/user/*""#)
+    )]
+    #[case(
+        r#"permit(principal, action, resource) when { resource.path like "/{{claims.department}}/*" };"#,
+        None
+    )]
+    #[tokio::test]
+    async fn test_templates_are_instantiated(
+        #[case] template: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        let fixture = token_service().await;
+        fixture
+            .policies
+            .upsert("home".to_string(), Policy::template(template.to_string()))
+            .await
+            .unwrap();
+        fixture
+            .attachments
+            .upsert(
+                ExternalIdentity::new(PROVIDER.to_string(), "user".to_string()),
+                PolicyAttachment::single("home".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let result = fixture
+            .service
+            .issue_token(
+                ExternalIdentityProvider::from(PROVIDER.to_string()),
+                ExternalToken::from("external".to_string()),
+                None,
+                false,
+            )
+            .await;
+        match expected {
+            Some(instantiated) => {
+                let details = details_of(result);
+                assert_eq!(details.policy_ids, vec!["home", "reader"]);
+                assert!(details.policy.contains(instantiated));
+                assert!(!details.policy.contains("{{"));
+            }
+            None => assert!(result.is_err()),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_pinned_revisions_of_deleted_policies_are_skipped() {
+        let fixture = token_service().await;
+        fixture
+            .attachments
+            .upsert(
+                ExternalIdentity::new(PROVIDER.to_string(), "user".to_string()),
+                PolicyAttachment::pinned("reader".to_string(), Some(1)),
+            )
+            .await
+            .unwrap();
+        fixture
+            .policies
+            .upsert(
+                "reader".to_string(),
+                Policy::new("forbid(principal, action, resource);".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let pinned = details(&issue(&fixture.service, None).await);
+        assert_eq!(pinned.policy.trim(), "permit(principal, action, resource);");
+
+        fixture.policies.delete("reader".to_string()).await.unwrap();
+        let result = fixture
+            .service
+            .issue_token(
+                ExternalIdentityProvider::from(PROVIDER.to_string()),
+                ExternalToken::from("external".to_string()),
+                None,
+                false,
+            )
+            .await;
+        assert!(is_invalid_grant(result));
+    }
 }